@@ -1,7 +1,11 @@
 use log::error;
 
-use super::mem::{Address, MemDevice, Ram, RNG_SND_WAV_RAM};
+use super::mem::{Address, MemDevice, RNG_SND_WAV_RAM};
+use super::system::SystemMode;
 
+mod blip;
+mod envelope;
+mod length;
 mod mixer;
 mod noise;
 mod square;
@@ -13,6 +17,8 @@ const REG_NR11: Address = Address(0xFF11);
 const REG_NR12: Address = Address(0xFF12);
 const REG_NR13: Address = Address(0xFF13);
 const REG_NR14: Address = Address(0xFF14);
+// Unused slots before the second and fourth channels' registers
+const REG_NR20: Address = Address(0xFF15);
 const REG_NR21: Address = Address(0xFF16);
 const REG_NR22: Address = Address(0xFF17);
 const REG_NR23: Address = Address(0xFF18);
@@ -22,6 +28,7 @@ const REG_NR31: Address = Address(0xFF1B);
 const REG_NR32: Address = Address(0xFF1C);
 const REG_NR33: Address = Address(0xFF1D);
 const REG_NR34: Address = Address(0xFF1E);
+const REG_NR40: Address = Address(0xFF1F);
 const REG_NR41: Address = Address(0xFF20);
 const REG_NR42: Address = Address(0xFF21);
 const REG_NR43: Address = Address(0xFF22);
@@ -30,23 +37,35 @@ const REG_NR50: Address = Address(0xFF24);
 const REG_NR51: Address = Address(0xFF25);
 const REG_NR52: Address = Address(0xFF26);
 
+// Bits that always read back as 1, either unused or write-only
+const NR10_UNUSED_BITS: u8 = 0b1000_0000;
+const NRX1_UNUSED_BITS: u8 = 0b0011_1111;
+const NRX4_UNUSED_BITS: u8 = 0b1011_1111;
+const NR30_UNUSED_BITS: u8 = 0b0111_1111;
+const NR32_UNUSED_BITS: u8 = 0b1001_1111;
+const NR52_UNUSED_BITS: u8 = 0b0111_0000;
+const NR52_POWER: u8 = 0b1000_0000;
+
+// Where the boot ROM leaves things
+const POST_BOOT_REGISTERS: [(Address, u8); 5] = [
+    (REG_NR52, 0x80),
+    (REG_NR11, 0x80),
+    (REG_NR12, 0xF3),
+    (REG_NR50, 0x77),
+    (REG_NR51, 0xF3),
+];
+
 pub struct Audio {
-    wav: Ram,
     nr10: u8,
     nr11: u8,
     nr12: u8,
-    nr13: u8,
     nr14: u8,
     nr21: u8,
     nr22: u8,
-    nr23: u8,
     nr24: u8,
     nr30: u8,
-    nr31: u8,
     nr32: u8,
-    nr33: u8,
     nr34: u8,
-    nr41: u8,
     nr42: u8,
     nr43: u8,
     nr44: u8,
@@ -54,6 +73,10 @@ pub struct Audio {
     nr51: u8,
     nr52: u8,
 
+    // These quirks belong to the console rather than the mode it runs in, so
+    // a CGB keeps them in DMG compatibility mode
+    cgb_hardware: bool,
+
     pub synth: synth::Synth,
 }
 
@@ -76,24 +99,23 @@ impl AudioSink for NullSink {
 }
 
 impl Audio {
-    pub fn new(sink: Box<dyn AudioSink + Send>) -> Audio {
-        Audio {
-            wav: Ram::new(RNG_SND_WAV_RAM.len()),
+    pub fn new(sink: Box<dyn AudioSink + Send>, cgb_mode: bool) -> Audio {
+        let mode = if cgb_mode {
+            SystemMode::CGB
+        } else {
+            SystemMode::DMG
+        };
+        let mut audio = Audio {
             nr10: 0,
             nr11: 0,
             nr12: 0,
-            nr13: 0,
             nr14: 0,
             nr21: 0,
             nr22: 0,
-            nr23: 0,
             nr24: 0,
             nr30: 0,
-            nr31: 0,
             nr32: 0,
-            nr33: 0,
             nr34: 0,
-            nr41: 0,
             nr42: 0,
             nr43: 0,
             nr44: 0,
@@ -101,7 +123,60 @@ impl Audio {
             nr51: 0,
             nr52: 0,
 
-            synth: synth::Synth::new(sink),
+            cgb_hardware: cgb_mode,
+
+            synth: synth::Synth::new(sink, mode),
+        };
+        for (a, v) in POST_BOOT_REGISTERS.iter() {
+            audio.write(*a, *v).unwrap();
+        }
+        audio
+    }
+
+    pub fn reset(&mut self) {
+        self.write(REG_NR52, 0).unwrap();
+    }
+
+    fn is_powered(&self) -> bool {
+        self.nr52 & NR52_POWER != 0
+    }
+
+    fn power_off(&mut self) {
+        self.nr10 = 0;
+        self.nr11 = 0;
+        self.nr12 = 0;
+        self.nr14 = 0;
+        self.nr21 = 0;
+        self.nr22 = 0;
+        self.nr24 = 0;
+        self.nr30 = 0;
+        self.nr32 = 0;
+        self.nr34 = 0;
+        self.nr42 = 0;
+        self.nr43 = 0;
+        self.nr44 = 0;
+        self.nr50 = 0;
+        self.nr51 = 0;
+        self.nr52 = 0;
+
+        self.synth
+            .mixer
+            .set_enabled_channels([false; 4], [false; 4]);
+        self.synth.mixer.set_master_volumes(0, 0);
+        self.synth.power_off(!self.cgb_hardware);
+    }
+
+    // The DMG still lets length counters be loaded with the power off
+    fn write_length_while_off(&mut self, a: Address, v: u8) {
+        if self.cgb_hardware {
+            return;
+        }
+        match a {
+            REG_NR11 => self.synth.chan1.length.load(v & 0b0011_1111),
+            REG_NR21 => self.synth.chan2.length.load(v & 0b0011_1111),
+            REG_NR31 => self.synth.chan3.set_length(v),
+            REG_NR41 => self.synth.chan4.set_length(v),
+            _ => {}
         }
     }
 }
@@ -109,44 +184,50 @@ impl Audio {
 impl MemDevice for Audio {
     fn read(&self, a: Address) -> Result<u8, ()> {
         if a.in_(RNG_SND_WAV_RAM) {
-            self.wav.read(a - RNG_SND_WAV_RAM.0)
+            // The DMG only lets the CPU at wave RAM in the cycle the channel
+            // reads it, which isn't modelled
+            if self.synth.chan3.is_enabled() && !self.cgb_hardware {
+                return Ok(0xFF);
+            }
+            let offset = a - RNG_SND_WAV_RAM.0;
+            Ok(self.synth.chan3.read_ram(offset.into()))
         } else {
             match a {
-                REG_NR10 => Ok(self.nr10),
-                REG_NR11 => Ok(self.nr11),
+                REG_NR10 => Ok(self.nr10 | NR10_UNUSED_BITS),
+                REG_NR11 => Ok(self.nr11 | NRX1_UNUSED_BITS),
                 REG_NR12 => Ok(self.nr12),
-                REG_NR14 => Ok(self.nr14),
-                REG_NR21 => Ok(self.nr21),
+                REG_NR14 => Ok(self.nr14 | NRX4_UNUSED_BITS),
+                REG_NR21 => Ok(self.nr21 | NRX1_UNUSED_BITS),
                 REG_NR22 => Ok(self.nr22),
-                REG_NR23 => Ok(self.nr23),
-                REG_NR24 => Ok(self.nr24),
-                REG_NR30 => Ok(self.nr30),
-                REG_NR31 => Ok(self.nr31),
-                REG_NR32 => Ok(self.nr32),
-                REG_NR33 => Ok(self.nr33),
-                REG_NR34 => Ok(self.nr34),
-                REG_NR41 => Ok(self.nr41),
+                REG_NR24 => Ok(self.nr24 | NRX4_UNUSED_BITS),
+                REG_NR30 => Ok(self.nr30 | NR30_UNUSED_BITS),
+                REG_NR32 => Ok(self.nr32 | NR32_UNUSED_BITS),
+                REG_NR34 => Ok(self.nr34 | NRX4_UNUSED_BITS),
                 REG_NR42 => Ok(self.nr42),
                 REG_NR43 => Ok(self.nr43),
-                REG_NR44 => Ok(self.nr44),
+                REG_NR44 => Ok(self.nr44 | NRX4_UNUSED_BITS),
                 REG_NR50 => Ok(self.nr50),
                 REG_NR51 => Ok(self.nr51),
                 REG_NR52 => {
-                    let mut v = 0b1000_0000 & self.nr52;
-                    if self.synth.chan1.is_active() {
+                    let mut v = self.nr52 | NR52_UNUSED_BITS;
+                    if self.synth.chan1.is_enabled() {
                         v |= 0b0000_0001;
                     }
-                    if self.synth.chan2.is_active() {
+                    if self.synth.chan2.is_enabled() {
                         v |= 0b0000_0010;
                     }
-                    if self.synth.chan3.is_active() {
+                    if self.synth.chan3.is_enabled() {
                         v |= 0b0000_0100;
                     }
-                    if self.synth.chan4.is_active() {
+                    if self.synth.chan4.is_enabled() {
                         v |= 0b0000_1000;
                     }
                     Ok(v)
                 }
+                // Write-only, or nothing behind them
+                REG_NR13 | REG_NR20 | REG_NR23 | REG_NR31 | REG_NR33 | REG_NR40 | REG_NR41 => {
+                    Ok(0xFF)
+                }
                 _ => {
                     error!("Unimplemented sound register {:?}", a);
                     Err(())
@@ -157,198 +238,246 @@ impl MemDevice for Audio {
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
         if a.in_(RNG_SND_WAV_RAM) {
+            if self.synth.chan3.is_enabled() && !self.cgb_hardware {
+                return Ok(());
+            }
             let offset = a - RNG_SND_WAV_RAM.0;
-            self.wav.write(offset, v).unwrap();
-            self.synth
-                .chan3
-                .write_sample(bits_to_sample(v & 0b1111), offset.0 as usize * 2 + 1);
-            self.synth
-                .chan3
-                .write_sample(bits_to_sample(v >> 4), offset.0 as usize * 2);
-            Ok(())
-        } else {
-            match a {
-                REG_NR10 => {
-                    self.nr10 = v;
-                    self.synth.chan1.set_freqeuncy_sweepers(
-                        v >> 4 & 0b111,
-                        v & 0b111,
-                        v & 0b1000 == 0,
-                    );
-                    Ok(())
-                }
-                REG_NR11 => {
-                    self.nr11 = v;
-                    self.synth.chan1.set_duty_cycle(v >> 6);
-                    self.synth.chan1.update_length(v & 0b0011_1111);
-                    Ok(())
-                }
-                REG_NR12 => {
-                    self.nr12 = v;
-                    self.synth.chan1.set_vol_env_period(v & 0b111);
-                    self.synth.chan1.increment_vol_env(v & 0b1000 != 0);
-                    self.synth.chan1.set_volume(v >> 4);
-                    Ok(())
-                }
-                REG_NR13 => {
-                    self.nr13 = v;
-                    self.synth
-                        .chan1
-                        .set_frequency_from_bits(self.nr14, self.nr13);
-                    Ok(())
-                }
-                REG_NR14 => {
-                    self.nr14 = v;
-                    self.synth
-                        .chan1
-                        .set_frequency_from_bits(self.nr14, self.nr13);
-                    self.synth.chan1.use_length_counter(v & 0b0100_0000 != 0);
-                    if v & 0b1000_0000 != 0 {
-                        self.synth.chan1.reset();
-                    }
-                    Ok(())
-                }
-                REG_NR21 => {
-                    self.nr21 = v;
-                    self.synth.chan2.set_duty_cycle(v >> 6);
-                    self.synth.chan2.update_length(v & 0b0011_1111);
-                    Ok(())
-                }
-                REG_NR22 => {
-                    self.nr22 = v;
-                    self.synth.chan2.set_vol_env_period(v & 0b111);
-                    self.synth.chan2.increment_vol_env(v & 0b1000 != 0);
-                    self.synth.chan2.set_volume(v >> 4);
-                    Ok(())
-                }
-                REG_NR23 => {
-                    self.nr23 = v;
-                    self.synth
-                        .chan2
-                        .set_frequency_from_bits(self.nr24, self.nr23);
-                    Ok(())
-                }
-                REG_NR24 => {
-                    self.nr24 = v;
-                    self.synth
-                        .chan2
-                        .set_frequency_from_bits(self.nr24, self.nr23);
-                    self.synth.chan2.use_length_counter(v & 0b0100_0000 != 0);
-                    if v & 0b1000_0000 != 0 {
-                        self.synth.chan2.reset();
-                    }
-                    Ok(())
-                }
-                REG_NR30 => {
-                    self.nr30 = v;
-                    self.synth.chan3.enabled = v & 0b1000_0000 != 0;
-                    Ok(())
-                }
-                REG_NR31 => {
-                    self.nr31 = v;
-                    self.synth.chan3.set_len(v);
-                    Ok(())
-                }
-                REG_NR32 => {
-                    self.nr32 = v;
-                    self.synth.chan3.vol_multiplier = match (v >> 5) & 0b11 {
-                        0 => 0.,
-                        1 => 1.,
-                        2 => 0.5,
-                        3 => 0.25,
-                        _ => unreachable!(),
-                    };
-                    Ok(())
-                }
-                REG_NR33 => {
-                    self.nr33 = v;
-                    self.synth
-                        .chan3
-                        .set_frequency_from_bits(self.nr34, self.nr33);
-                    Ok(())
-                }
-                REG_NR34 => {
-                    self.nr34 = v;
-                    self.synth
-                        .chan3
-                        .set_frequency_from_bits(self.nr34, self.nr33);
-                    self.synth.chan3.use_len = v & 0b0100_0000 != 0;
-                    if v & 0b1000_0000 != 0 {
-                        self.synth.chan3.reset();
-                    }
-                    Ok(())
-                }
-                REG_NR41 => {
-                    self.nr41 = v;
-                    self.synth.chan4.set_len(v & 0b0011_1111);
-                    Ok(())
-                }
-                REG_NR42 => {
-                    self.nr42 = v;
-                    self.synth.chan4.set_vol_env_period(v & 0b111);
-                    self.synth.chan4.increment_vol_env(v & 0b1000 != 0);
-                    self.synth.chan4.set_volume(v >> 4);
-                    Ok(())
-                }
-                REG_NR43 => {
-                    self.nr43 = v;
-                    self.synth.chan4.set_frequency_from_bits(v);
-                    Ok(())
-                }
-                REG_NR44 => {
-                    self.nr44 = v;
-                    self.synth.chan4.use_len = v & 0b0100_0000 != 0;
-                    if v & 0b1000_0000 != 0 {
-                        self.synth.chan4.reset();
+            self.synth.chan3.write_ram(offset.into(), v);
+            return Ok(());
+        }
+
+        if !self.is_powered() && a != REG_NR52 {
+            self.write_length_while_off(a, v);
+            return Ok(());
+        }
+
+        let extra_length_clock = self.synth.extra_length_clock();
+        match a {
+            REG_NR10 => {
+                self.nr10 = v;
+                self.synth.chan1.set_sweep(v);
+            }
+            REG_NR11 => {
+                self.nr11 = v;
+                self.synth.chan1.set_duty_and_length(v);
+            }
+            REG_NR12 => {
+                self.nr12 = v;
+                self.synth.chan1.set_envelope(v);
+            }
+            REG_NR13 => {
+                self.synth.chan1.set_frequency_low(v);
+            }
+            REG_NR14 => {
+                self.nr14 = v;
+                self.synth.chan1.set_control(v, extra_length_clock);
+            }
+            REG_NR21 => {
+                self.nr21 = v;
+                self.synth.chan2.set_duty_and_length(v);
+            }
+            REG_NR22 => {
+                self.nr22 = v;
+                self.synth.chan2.set_envelope(v);
+            }
+            REG_NR23 => {
+                self.synth.chan2.set_frequency_low(v);
+            }
+            REG_NR24 => {
+                self.nr24 = v;
+                self.synth.chan2.set_control(v, extra_length_clock);
+            }
+            REG_NR30 => {
+                self.nr30 = v;
+                self.synth.chan3.set_dac(v);
+            }
+            REG_NR31 => {
+                self.synth.chan3.set_length(v);
+            }
+            REG_NR32 => {
+                self.nr32 = v;
+                self.synth.chan3.set_volume(v);
+            }
+            REG_NR33 => {
+                self.synth.chan3.set_frequency_low(v);
+            }
+            REG_NR34 => {
+                self.nr34 = v;
+                self.synth.chan3.set_control(v, extra_length_clock);
+            }
+            REG_NR41 => {
+                self.synth.chan4.set_length(v);
+            }
+            REG_NR42 => {
+                self.nr42 = v;
+                self.synth.chan4.set_envelope(v);
+            }
+            REG_NR43 => {
+                self.nr43 = v;
+                self.synth.chan4.set_frequency(v);
+            }
+            REG_NR44 => {
+                self.nr44 = v;
+                self.synth.chan4.set_control(v, extra_length_clock);
+            }
+            REG_NR50 => {
+                self.nr50 = v;
+                self.synth
+                    .mixer
+                    .set_master_volumes((v >> 4) & 0b111, v & 0b111);
+            }
+            REG_NR51 => {
+                self.nr51 = v;
+                self.synth.mixer.set_enabled_channels(
+                    [
+                        v & 0b0000_0001 != 0,
+                        v & 0b0000_0010 != 0,
+                        v & 0b0000_0100 != 0,
+                        v & 0b0000_1000 != 0,
+                    ],
+                    [
+                        v & 0b0001_0000 != 0,
+                        v & 0b0010_0000 != 0,
+                        v & 0b0100_0000 != 0,
+                        v & 0b1000_0000 != 0,
+                    ],
+                );
+            }
+            REG_NR52 => {
+                if v & NR52_POWER == 0 {
+                    if self.is_powered() {
+                        self.power_off();
                     }
-                    Ok(())
-                }
-                REG_NR50 => {
-                    self.nr50 = v;
-                    self.synth.mixer.set_master_volumes(
-                        f32::from((v >> 4) & 0b111) / 7.,
-                        f32::from(v & 0b111) / 7.,
-                    );
-                    Ok(())
-                }
-                REG_NR51 => {
-                    self.nr51 = v;
-                    self.synth.mixer.set_enabled_channels(
-                        [
-                            v & 0b0000_0001 != 0,
-                            v & 0b0000_0010 != 0,
-                            v & 0b0000_0100 != 0,
-                            v & 0b0000_1000 != 0,
-                        ],
-                        [
-                            v & 0b0001_0000 != 0,
-                            v & 0b0010_0000 != 0,
-                            v & 0b0100_0000 != 0,
-                            v & 0b1000_0000 != 0,
-                        ],
-                    );
-                    Ok(())
-                }
-                REG_NR52 => {
-                    self.nr52 = v;
-                    Ok(())
-                }
-                _ => {
-                    error!("Unimplemented sound register {:?}", a);
-                    Err(())
+                } else if !self.is_powered() {
+                    self.nr52 = NR52_POWER;
+                    self.synth.power_on();
                 }
             }
+            REG_NR20 | REG_NR40 => {}
+            _ => {
+                error!("Unimplemented sound register {:?}", a);
+                return Err(());
+            }
         }
+        self.synth.update_levels();
+        Ok(())
     }
 }
 
-fn bits_to_sample(b: u8) -> f32 {
-    (f32::from(b) - 8.) / 8.
+#[cfg(test)]
+fn make_test_audio(cgb_mode: bool) -> Audio {
+    Audio::new(Box::new(NullSink), cgb_mode)
+}
+
+#[cfg(test)]
+fn channel_status(audio: &Audio) -> u8 {
+    audio.read(REG_NR52).unwrap() & 0b1111
 }
 
 #[test]
-fn test_bits_to_sample() {
-    assert_eq!(bits_to_sample(0), -1.);
-    assert_eq!(bits_to_sample(8), 0.);
-    assert_eq!(bits_to_sample(16), 1.);
+fn test_register_read_masks() {
+    let mut audio = make_test_audio(false);
+    for a in REG_NR10.0..REG_NR52.0 {
+        audio.write(Address(a), 0).unwrap();
+    }
+
+    let expected = [
+        0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+        0xFF, 0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0xF0,
+    ];
+    for (i, v) in expected.iter().enumerate() {
+        let a = REG_NR10 + Address(i as u16);
+        assert_eq!(audio.read(a), Ok(*v), "{}", a);
+    }
+}
+
+#[test]
+fn test_power_off() {
+    let mut audio = make_test_audio(true);
+    audio.write(REG_NR12, 0xF0).unwrap();
+    audio.write(REG_NR14, 0x80).unwrap();
+    assert_eq!(channel_status(&audio), 0b0001);
+
+    audio.write(REG_NR52, 0).unwrap();
+    assert_eq!(audio.read(REG_NR52), Ok(0x70));
+    assert_eq!(audio.read(REG_NR50), Ok(0));
+    assert_eq!(audio.read(REG_NR12), Ok(0));
+
+    // Only wave RAM can be written until the power comes back
+    audio.write(REG_NR50, 0x77).unwrap();
+    audio.write(RNG_SND_WAV_RAM.0, 0x12).unwrap();
+    assert_eq!(audio.read(REG_NR50), Ok(0));
+    assert_eq!(audio.read(RNG_SND_WAV_RAM.0), Ok(0x12));
+
+    audio.write(REG_NR52, 0x80).unwrap();
+    audio.write(REG_NR50, 0x77).unwrap();
+    assert_eq!(audio.read(REG_NR50), Ok(0x77));
+}
+
+#[test]
+fn test_length_while_off() {
+    for cgb_mode in [false, true].iter() {
+        let mut audio = make_test_audio(*cgb_mode);
+        audio.write(REG_NR52, 0).unwrap();
+        audio.write(REG_NR11, 0x3F).unwrap();
+        audio.write(REG_NR52, 0x80).unwrap();
+
+        audio.write(REG_NR12, 0xF0).unwrap();
+        audio.write(REG_NR14, 0xC0).unwrap();
+        audio.synth.pump_cycle(synth::FRAME_SEQUENCER_PERIOD);
+
+        // Only the DMG kept the length of 1, which runs out on the first step
+        let expected = if *cgb_mode { 0b0001 } else { 0b0000 };
+        assert_eq!(channel_status(&audio), expected);
+    }
+}
+
+#[test]
+fn test_extra_length_clock() {
+    let mut audio = make_test_audio(false);
+    audio.write(REG_NR21, 0x3F).unwrap();
+    audio.write(REG_NR22, 0xF0).unwrap();
+    audio.write(REG_NR24, 0x80).unwrap();
+
+    // The next step doesn't clock length, so enabling it clocks it right away
+    audio.synth.pump_cycle(synth::FRAME_SEQUENCER_PERIOD);
+    assert_eq!(channel_status(&audio), 0b0010);
+    audio.write(REG_NR24, 0x40).unwrap();
+    assert_eq!(channel_status(&audio), 0b0000);
+}
+
+#[test]
+fn test_dac_disables_channel() {
+    let mut audio = make_test_audio(false);
+    audio.write(REG_NR30, 0x80).unwrap();
+    audio.write(REG_NR34, 0x80).unwrap();
+    assert_eq!(channel_status(&audio), 0b0100);
+
+    audio.write(REG_NR30, 0).unwrap();
+    assert_eq!(channel_status(&audio), 0b0000);
+    audio.write(REG_NR34, 0x80).unwrap();
+    assert_eq!(channel_status(&audio), 0b0000);
+}
+
+#[test]
+fn test_sweep_overflow_on_trigger() {
+    let mut audio = make_test_audio(false);
+    audio.write(REG_NR10, 0x01).unwrap();
+    audio.write(REG_NR12, 0xF0).unwrap();
+    audio.write(REG_NR13, 0xFF).unwrap();
+    audio.write(REG_NR14, 0x87).unwrap();
+    assert_eq!(channel_status(&audio), 0b0000);
+}
+
+#[test]
+fn test_sweep_negate_quirk() {
+    let mut audio = make_test_audio(false);
+    audio.write(REG_NR10, 0x19).unwrap();
+    audio.write(REG_NR12, 0xF0).unwrap();
+    audio.write(REG_NR14, 0x84).unwrap();
+    assert_eq!(channel_status(&audio), 0b0001);
+
+    // The trigger already subtracted once, so leaving negate mode cuts it off
+    audio.write(REG_NR10, 0x11).unwrap();
+    assert_eq!(channel_status(&audio), 0b0000);
 }
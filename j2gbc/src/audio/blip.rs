@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::cpu::CLOCK_RATE;
+
+// Each change in level is spread over this many output samples as a
+// windowed-sinc step, so nothing above half the sample rate aliases back down
+const STEP_WIDTH: usize = 32;
+// Steps are placed to within 1/STEP_PHASES of an output sample
+const STEP_PHASES: u64 = 64;
+// Where the band stops, as a fraction of the sample rate. Just short of the
+// Nyquist frequency, leaving room for the window's roll-off
+const CUTOFF: f64 = 0.42;
+
+// Collects level changes at exact cycles and turns them into samples at any
+// sample rate, fractional clocks per sample included
+pub struct BlipBuffer {
+    sample_rate: u64,
+    steps: Vec<[f64; STEP_WIDTH]>,
+
+    // Absolute index of the next sample to be read, which the front of
+    // `deltas` lines up with
+    next_sample: u64,
+    deltas: VecDeque<f64>,
+    level: f64,
+}
+
+impl BlipBuffer {
+    pub fn new(sample_rate: u64) -> BlipBuffer {
+        BlipBuffer {
+            sample_rate,
+            steps: make_steps(),
+
+            next_sample: 0,
+            deltas: VecDeque::new(),
+            level: 0.,
+        }
+    }
+
+    // The sample a cycle falls in, and how far into it
+    fn sample_position(&self, cycle: u64) -> (u64, usize) {
+        let position = u128::from(cycle) * u128::from(self.sample_rate);
+        let clock_rate = u128::from(CLOCK_RATE);
+        let phase = position % clock_rate * u128::from(STEP_PHASES) / clock_rate;
+        ((position / clock_rate) as u64, phase as usize)
+    }
+
+    pub fn add_delta(&mut self, cycle: u64, delta: f32) {
+        let (sample, phase) = self.sample_position(cycle);
+        let start = (sample - self.next_sample) as usize;
+        if self.deltas.len() < start + STEP_WIDTH {
+            self.deltas.resize(start + STEP_WIDTH, 0.);
+        }
+
+        for (i, tap) in self.steps[phase].iter().enumerate() {
+            self.deltas[start + i] += f64::from(delta) * tap;
+        }
+    }
+
+    // Samples from before a cycle can't be changed by anything added at or
+    // after it, so they're ready to read
+    pub fn samples_available(&self, cycle: u64) -> u64 {
+        self.sample_position(cycle).0 - self.next_sample
+    }
+
+    pub fn read_sample(&mut self) -> f32 {
+        self.level += self.deltas.pop_front().unwrap_or(0.);
+        self.next_sample += 1;
+        self.level as f32
+    }
+}
+
+// One step per phase, each summing to 1 so that a delta moves the level by
+// exactly that much once it has passed
+fn make_steps() -> Vec<[f64; STEP_WIDTH]> {
+    let half_width = (STEP_WIDTH / 2) as f64;
+
+    (0..STEP_PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / STEP_PHASES as f64;
+            let mut step = [0.; STEP_WIDTH];
+            for (i, tap) in step.iter_mut().enumerate() {
+                let x = i as f64 + 1. - half_width - offset;
+                *tap = sinc(2. * CUTOFF * x) * blackman(x / half_width);
+            }
+
+            let sum: f64 = step.iter().sum();
+            for tap in step.iter_mut() {
+                *tap /= sum;
+            }
+            step
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0. {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Runs from 1 in the middle to 0 at -1 and 1
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2. * PI * x).cos()
+}
+
+#[test]
+fn test_fractional_sample_rate() {
+    let buffer = BlipBuffer::new(44_100);
+    assert_eq!(buffer.samples_available(CLOCK_RATE), 44_100);
+    assert_eq!(buffer.samples_available(CLOCK_RATE * 3 / 2), 66_150);
+}
+
+#[test]
+fn test_step() {
+    let mut buffer = BlipBuffer::new(48_000);
+    buffer.add_delta(1000, 1.);
+    buffer.add_delta(2000, -0.5);
+
+    let count = buffer.samples_available(CLOCK_RATE / 100);
+    let samples: Vec<f32> = (0..count).map(|_| buffer.read_sample()).collect();
+    assert_eq!(samples[0], 0.);
+    assert!((samples[samples.len() - 1] - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn test_step_phase() {
+    // Steps a fraction of a sample apart come out differently
+    let mut early = BlipBuffer::new(48_000);
+    let mut late = BlipBuffer::new(48_000);
+    early.add_delta(1000, 1.);
+    late.add_delta(1040, 1.);
+
+    let early_sample = (0..27).map(|_| early.read_sample()).last();
+    let late_sample = (0..27).map(|_| late.read_sample()).last();
+    assert!(early_sample > late_sample);
+}
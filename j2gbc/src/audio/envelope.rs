@@ -0,0 +1,63 @@
+// Steps a channel's volume up or down on the frame sequencer's envelope step
+#[derive(Clone, Copy, Default)]
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope::default()
+    }
+
+    pub fn write(&mut self, v: u8) {
+        self.initial_volume = v >> 4;
+        self.increase = v & 0b1000 != 0;
+        self.period = v & 0b111;
+    }
+
+    // The DAC is on as long as any of the top five bits of NRx2 are set
+    pub fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = reload_period(self.period);
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
+
+// Envelope and sweep timers treat a period of 0 as 8
+pub fn reload_period(period: u8) -> u8 {
+    if period == 0 {
+        8
+    } else {
+        period
+    }
+}
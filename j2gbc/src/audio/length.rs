@@ -0,0 +1,49 @@
+// Counts down on the frame sequencer's length steps while enabled, and
+// silences its channel when it runs out
+#[derive(Clone, Copy, Default)]
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn load(&mut self, len: u8) {
+        self.counter = self.max - u16::from(len);
+    }
+
+    // Returns true if the counter just ran out
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter != 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    // Enabling the counter when the next frame sequencer step won't clock it
+    // clocks it straight away. Returns true if that made it run out
+    pub fn set_enabled(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        !was_enabled && extra_clock && self.clock()
+    }
+
+    pub fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if extra_clock {
+                self.clock();
+            }
+        }
+    }
+}
@@ -1,24 +1,55 @@
-#[derive(Default)]
+use std::f32::consts::PI;
+
+use crate::cpu::CLOCK_RATE;
+use crate::system::SystemMode;
+
+// How much charge the output capacitors keep each clock. The CGB's leak
+// faster, so it loses a DC offset sooner
+const DMG_CHARGE_FACTOR: f64 = 0.999_958;
+const CGB_CHARGE_FACTOR: f64 = 0.998_943;
+// Where the output stage starts to roll off the highs, when that's modelled
+const LOW_PASS_CUTOFF: f32 = 14_000.;
+
 pub struct Mixer {
     left_enable: [bool; 4],
     right_enable: [bool; 4],
 
     left_master_vol: f32,
     right_master_vol: f32,
+
+    charge_factor: f32,
+    capacitors: (f32, f32),
+
+    low_pass: bool,
+    low_pass_factor: f32,
+    low_pass_levels: (f32, f32),
 }
 
 impl Mixer {
-    pub fn new() -> Mixer {
+    pub fn new(mode: SystemMode, sample_rate: u64) -> Mixer {
+        let charge_factor = match mode {
+            SystemMode::DMG => DMG_CHARGE_FACTOR,
+            SystemMode::CGB => CGB_CHARGE_FACTOR,
+        };
+        let clocks_per_sample = CLOCK_RATE as f64 / sample_rate as f64;
+
         Mixer {
             left_enable: [false; 4],
             right_enable: [false; 4],
 
             left_master_vol: 0.,
             right_master_vol: 0.,
+
+            charge_factor: charge_factor.powf(clocks_per_sample) as f32,
+            capacitors: (0., 0.),
+
+            low_pass: false,
+            low_pass_factor: 1. - (-2. * PI * LOW_PASS_CUTOFF / sample_rate as f32).exp(),
+            low_pass_levels: (0., 0.),
         }
     }
 
-    pub fn mix(&self, samples: [f32; 4]) -> (f32, f32) {
+    pub fn mix(&mut self, samples: [f32; 4]) -> (f32, f32) {
         let left_val: f32 = samples
             .iter()
             .zip(self.left_enable.iter())
@@ -30,10 +61,27 @@ impl Mixer {
             .map(|(sample, enabled)| if *enabled { *sample } else { 0. })
             .sum();
 
-        (
-            left_val / 4. * self.left_master_vol,
-            right_val / 4. * self.right_master_vol,
-        )
+        let left = self.filter_left(left_val / 4. * self.left_master_vol);
+        let right = self.filter_right(right_val / 4. * self.right_master_vol);
+        (left, right)
+    }
+
+    fn filter_left(&mut self, v: f32) -> f32 {
+        let v = high_pass(&mut self.capacitors.0, self.charge_factor, v);
+        if self.low_pass {
+            low_pass(&mut self.low_pass_levels.0, self.low_pass_factor, v)
+        } else {
+            v
+        }
+    }
+
+    fn filter_right(&mut self, v: f32) -> f32 {
+        let v = high_pass(&mut self.capacitors.1, self.charge_factor, v);
+        if self.low_pass {
+            low_pass(&mut self.low_pass_levels.1, self.low_pass_factor, v)
+        } else {
+            v
+        }
     }
 
     pub fn set_enabled_channels(&mut self, left_enable: [bool; 4], right_enable: [bool; 4]) {
@@ -41,8 +89,66 @@ impl Mixer {
         self.right_enable = right_enable;
     }
 
-    pub fn set_master_volumes(&mut self, left: f32, right: f32) {
-        self.left_master_vol = left;
-        self.right_master_vol = right;
+    // NR50 volumes run from 1/8 to 8/8, so even 0 isn't silent
+    pub fn set_master_volumes(&mut self, left: u8, right: u8) {
+        self.left_master_vol = f32::from(left + 1) / 8.;
+        self.right_master_vol = f32::from(right + 1) / 8.;
+    }
+
+    pub fn set_low_pass(&mut self, low_pass: bool) {
+        self.low_pass = low_pass;
     }
 }
+
+// The capacitor on each output charges up to any DC offset and blocks it
+fn high_pass(capacitor: &mut f32, charge_factor: f32, v: f32) -> f32 {
+    let out = v - *capacitor;
+    *capacitor = v - out * charge_factor;
+    out
+}
+
+fn low_pass(level: &mut f32, factor: f32, v: f32) -> f32 {
+    *level += factor * (v - *level);
+    *level
+}
+
+#[cfg(test)]
+fn make_test_mixer(mode: SystemMode) -> Mixer {
+    let mut mixer = Mixer::new(mode, 48_000);
+    mixer.set_enabled_channels([true, false, false, false], [true, false, false, false]);
+    mixer.set_master_volumes(7, 0);
+    mixer
+}
+
+#[test]
+fn test_master_volume() {
+    let mut mixer = make_test_mixer(SystemMode::DMG);
+    assert_eq!(mixer.mix([1., 1., 1., 1.]), (0.25, 0.25 / 8.));
+}
+
+#[test]
+fn test_high_pass() {
+    let mut dmg = make_test_mixer(SystemMode::DMG);
+    let mut cgb = make_test_mixer(SystemMode::CGB);
+
+    // A constant offset drains away, faster on the CGB
+    let mut dmg_out = (0., 0.);
+    let mut cgb_out = (0., 0.);
+    for _ in 0..480 {
+        dmg_out = dmg.mix([1., 0., 0., 0.]);
+        cgb_out = cgb.mix([1., 0., 0., 0.]);
+    }
+    assert!(dmg_out.0 < 0.1);
+    assert!(cgb_out.0 < dmg_out.0);
+}
+
+#[test]
+fn test_low_pass() {
+    let mut mixer = make_test_mixer(SystemMode::CGB);
+    mixer.set_low_pass(true);
+
+    let (first, _) = mixer.mix([1., 0., 0., 0.]);
+    let (second, _) = mixer.mix([1., 0., 0., 0.]);
+    assert!(first > 0. && first < 0.25);
+    assert!(second > first);
+}
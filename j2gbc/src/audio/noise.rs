@@ -1,25 +1,21 @@
-use j2ds::Clock;
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+use super::synth::run_timer;
+
+const DIVISORS: [u64; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
 pub struct NoiseChannel {
+    enabled: bool,
     lfsr: u16,
-    lfsr_half: bool,
-
-    period: u64,
-
-    len: u8,
-    pub use_len: bool,
+    narrow: bool,
+    divisor: u8,
+    shift: u8,
+    timer: u64,
 
-    next_lfsr_shift_cycle: u64,
-    last_cpu_cycle: u64,
-
-    vol: u8,
-    vol_orig: u8,
-    vol_env_increment: bool,
-    vol_counter: Clock,
+    pub length: LengthCounter,
+    envelope: Envelope,
 }
 
-const DIVISORS: [u64; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
-
 impl Default for NoiseChannel {
     fn default() -> Self {
         Self::new()
@@ -29,102 +25,124 @@ impl Default for NoiseChannel {
 impl NoiseChannel {
     pub fn new() -> NoiseChannel {
         NoiseChannel {
-            lfsr: 0b1111_1111,
-            lfsr_half: false,
-
-            period: 0,
-
-            len: 0,
-            use_len: false,
-
-            next_lfsr_shift_cycle: 0,
-            last_cpu_cycle: 0,
+            enabled: false,
+            lfsr: 0x7FFF,
+            narrow: false,
+            divisor: 0,
+            shift: 0,
+            timer: DIVISORS[0],
+
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
 
-            vol: 0,
-            vol_orig: 0,
-            vol_env_increment: false,
-            vol_counter: Clock::new(0),
+    // The LFSR isn't clocked at all with a shift of 14 or 15
+    fn period(&self) -> Option<u64> {
+        if self.shift < 14 {
+            Some(DIVISORS[self.divisor as usize] << self.shift)
+        } else {
+            None
         }
     }
 
-    pub fn set_frequency_from_bits(&mut self, bits: u8) {
-        let s = (bits >> 4) & 0b1111;
-        let r = DIVISORS[(bits & 0b111) as usize];
-        self.period = r << s;
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = self.length;
+        *self = NoiseChannel::new();
+        if keep_length {
+            self.length = length;
+        }
+    }
 
-        self.lfsr_half = bits & 0b0000_1000 != 0;
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
     }
 
-    pub fn set_len(&mut self, len: u8) {
-        self.len = 64 - len;
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
     }
 
-    pub fn decrement_length(&mut self) {
-        if self.len > 0 {
-            self.len -= 1;
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0b1 == 0 {
+            self.envelope.volume()
+        } else {
+            0
         }
     }
 
-    pub fn set_volume(&mut self, vol: u8) {
-        self.vol = vol;
-        self.vol_orig = vol;
+    pub fn set_length(&mut self, v: u8) {
+        self.length.load(v & 0b0011_1111);
     }
 
-    pub fn set_vol_env_period(&mut self, p: u8) {
-        self.vol_counter = Clock::new(u64::from(p));
+    pub fn set_envelope(&mut self, v: u8) {
+        self.envelope.write(v);
+        if !self.dac_enabled() {
+            self.enabled = false;
+        }
     }
 
-    pub fn increment_vol_env(&mut self, inc: bool) {
-        self.vol_env_increment = inc;
+    pub fn set_frequency(&mut self, v: u8) {
+        self.shift = v >> 4;
+        self.narrow = v & 0b1000 != 0;
+        self.divisor = v & 0b111;
     }
 
-    pub fn volume_env_update(&mut self) {
-        if self.vol_counter.period() == 0 {
-            return;
+    pub fn set_control(&mut self, v: u8, extra_length_clock: bool) {
+        let trigger = v & 0b1000_0000 != 0;
+        if self
+            .length
+            .set_enabled(v & 0b0100_0000 != 0, extra_length_clock)
+            && !trigger
+        {
+            self.enabled = false;
         }
-
-        if self.vol_counter.tick() {
-            if self.vol_env_increment && self.vol < 15 {
-                self.vol += 1;
-            } else if self.vol != 0 {
-                self.vol -= 1;
-            }
+        if trigger {
+            self.trigger(extra_length_clock);
         }
     }
 
-    pub fn sample(&mut self, cpu_cycle: u64) -> f32 {
-        if self.period == 0 || !self.is_active() {
-            return 0.;
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger(extra_length_clock);
+        if let Some(period) = self.period() {
+            self.timer = period;
         }
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
 
-        while self.next_lfsr_shift_cycle <= cpu_cycle {
-            let shift = if self.lfsr_half { 6 } else { 14 };
-
-            let downshifted = self.lfsr >> 1;
-            let bit_1 = self.lfsr & 0b1;
-            let bit_2 = downshifted & 0b1;
-            let new_bit = (bit_1 ^ bit_2) << shift;
-            let cleared = downshifted & !(1 << shift);
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
 
-            self.lfsr = cleared | new_bit;
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
 
-            self.next_lfsr_shift_cycle += self.period;
+    // How long until the output might change
+    pub fn ticks_to_next_step(&self) -> u64 {
+        if self.enabled && self.period().is_some() {
+            self.timer
+        } else {
+            u64::MAX
         }
-        self.last_cpu_cycle = cpu_cycle;
-
-        (f32::from(self.vol) / 15.) * if self.lfsr & 0b1 != 0 { -1. } else { 1. }
     }
 
-    pub fn is_active(&self) -> bool {
-        !self.use_len || self.len > 0
+    pub fn advance(&mut self, ticks: u64) {
+        if let Some(period) = self.period() {
+            for _ in 0..run_timer(&mut self.timer, period, ticks) {
+                self.step_lfsr();
+            }
+        }
     }
 
-    pub fn reset(&mut self) {
-        if self.len == 0 {
-            self.len = 64;
+    fn step_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0b1;
+        self.lfsr = (self.lfsr >> 1) | bit << 14;
+        if self.narrow {
+            self.lfsr = self.lfsr & !(1 << 6) | bit << 6;
         }
-        self.next_lfsr_shift_cycle = self.period + self.last_cpu_cycle;
-        self.vol = self.vol_orig;
-        self.lfsr = 0b1111_1111;
     }
 }
@@ -1,33 +1,36 @@
-use j2ds::{Clock, Timer};
+use super::envelope::{reload_period, Envelope};
+use super::length::LengthCounter;
+use super::synth::run_timer;
+
+// One bit per duty step, played from the top bit down
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const MAX_FREQUENCY: u16 = 2047;
+
+#[derive(Default)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+
+    timer: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+    // Set once a calculation has subtracted since the last trigger
+    negated: bool,
+}
 
 pub struct SquareChannel {
-    period: u64,
-    duty_cycle: u8,
-    use_len: bool,
-    len: u8,
-    last_cpu_cycle: u64,
-    duty_cycle_step: usize,
-    duty_cycle_step_timer: Timer,
-    duty_cycle_step_timer_offset: u64,
-
-    vol: u8,
-    vol_orig: u8,
-    vol_env_increment: bool,
-    vol_counter: Clock,
-
-    frequency: u64,
-    frequency_shift: u8,
-    frequency_increment: bool,
-    frequency_sweep_counter: Clock,
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u64,
+
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep,
 }
 
-const DUTY_VALUES: [[f32; 8]; 4] = [
-    [-1., -1., -1., -1., -1., -1., -1., 1.],
-    [1., -1., -1., -1., -1., -1., -1., 1.],
-    [1., -1., -1., -1., -1., 1., 1., 1.],
-    [-1., 1., 1., 1., 1., 1., 1., -1.],
-];
-
 impl Default for SquareChannel {
     fn default() -> Self {
         Self::new()
@@ -36,144 +39,168 @@ impl Default for SquareChannel {
 
 impl SquareChannel {
     pub fn new() -> SquareChannel {
-        SquareChannel {
-            period: 0,
-            duty_cycle: 0,
-            duty_cycle_step: 0,
-            duty_cycle_step_timer: Timer::new(1, 0, 0),
-            duty_cycle_step_timer_offset: 0,
-            use_len: false,
-            len: 0,
-            last_cpu_cycle: 0,
-
-            vol: 0,
-            vol_orig: 0,
-            vol_env_increment: false,
-            vol_counter: Clock::new(0),
-
+        let mut chan = SquareChannel {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
             frequency: 0,
-            frequency_shift: 0,
-            frequency_increment: false,
-            frequency_sweep_counter: Clock::new(0),
-        }
-    }
+            timer: 0,
 
-    pub fn reset(&mut self) {
-        self.frequency_sweep_counter.reset();
-        self.vol_counter.reset();
-        if self.len == 0 {
-            self.len = 64;
-        }
-        self.vol = self.vol_orig;
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: Sweep::default(),
+        };
+        chan.timer = chan.period();
+        chan
     }
 
-    pub fn set_volume(&mut self, vol: u8) {
-        self.vol = vol;
-        self.vol_orig = vol;
+    fn period(&self) -> u64 {
+        (2048 - u64::from(self.frequency)) * 4
     }
 
-    pub fn set_vol_env_period(&mut self, p: u8) {
-        self.vol_counter = Clock::new(u64::from(p));
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = self.length;
+        *self = SquareChannel::new();
+        if keep_length {
+            self.length = length;
+        }
     }
 
-    pub fn increment_vol_env(&mut self, inc: bool) {
-        self.vol_env_increment = inc;
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
     }
 
-    pub fn set_freqeuncy_sweepers(
-        &mut self,
-        freqeuncy_period: u8,
-        freqeuncy_shift: u8,
-        freqeuncy_increment: bool,
-    ) {
-        self.frequency_sweep_counter = Clock::new(u64::from(freqeuncy_period));
-        self.frequency_shift = freqeuncy_shift;
-        self.frequency_increment = freqeuncy_increment;
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
     }
 
-    pub fn freq_sweep_update(&mut self) {
-        if self.frequency_sweep_counter.period() == 0 {
-            return;
+    pub fn output(&self) -> u8 {
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step) & 1 != 0;
+        if self.enabled && high {
+            self.envelope.volume()
+        } else {
+            0
         }
+    }
 
-        if self.frequency_sweep_counter.tick() {
-            let operand = self.frequency >> self.frequency_shift;
-            let mut new_f = self.frequency;
-            if self.frequency_increment {
-                new_f += operand;
-                if new_f > 2049 {
-                    new_f = 2049;
-                }
-            } else if self.frequency_shift != 0 && new_f >= operand {
-                new_f -= operand;
-            }
-            self.frequency = new_f;
-            self.update_from_frequency();
+    pub fn set_sweep(&mut self, v: u8) {
+        self.sweep.period = (v >> 4) & 0b111;
+        self.sweep.negate = v & 0b1000 != 0;
+        self.sweep.shift = v & 0b111;
+
+        // Leaving negate mode after it has been used cuts the channel off
+        if !self.sweep.negate && self.sweep.negated {
+            self.enabled = false;
         }
     }
 
-    pub fn set_frequency_from_bits(&mut self, hi: u8, lo: u8) {
-        self.frequency = (u64::from(hi) & 0b111) << 8 | u64::from(lo);
-        self.update_from_frequency();
+    pub fn set_duty_and_length(&mut self, v: u8) {
+        self.duty = v >> 6;
+        self.length.load(v & 0b0011_1111);
     }
 
-    fn update_from_frequency(&mut self) {
-        if self.frequency <= 2048 {
-            self.period = 4 * (2048 - self.frequency);
-            self.duty_cycle_step_timer = Timer::new(self.period, 0, 0);
-            self.duty_cycle_step_timer_offset = self.last_cpu_cycle;
+    pub fn set_envelope(&mut self, v: u8) {
+        self.envelope.write(v);
+        if !self.dac_enabled() {
+            self.enabled = false;
         }
     }
 
-    pub fn set_duty_cycle(&mut self, duty_cycle: u8) {
-        self.duty_cycle = duty_cycle;
+    pub fn set_frequency_low(&mut self, v: u8) {
+        self.frequency = self.frequency & 0x700 | u16::from(v);
     }
 
-    pub fn decrement_length(&mut self) {
-        if self.len > 0 {
-            self.len -= 1;
+    pub fn set_control(&mut self, v: u8, extra_length_clock: bool) {
+        self.frequency = self.frequency & 0xFF | u16::from(v & 0b111) << 8;
+
+        let trigger = v & 0b1000_0000 != 0;
+        if self
+            .length
+            .set_enabled(v & 0b0100_0000 != 0, extra_length_clock)
+            && !trigger
+        {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(extra_length_clock);
         }
     }
 
-    pub fn update_length(&mut self, len: u8) {
-        self.len = 64 - len;
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled();
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        self.sweep.shadow_frequency = self.frequency;
+        self.sweep.timer = reload_period(self.sweep.period);
+        self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+        self.sweep.negated = false;
+        if self.sweep.shift != 0 {
+            self.sweep_frequency();
+        }
     }
 
-    pub fn use_length_counter(&mut self, use_len: bool) {
-        self.use_len = use_len;
+    // Works out the next frequency from the shadow copy, and cuts the channel
+    // off if it overflows
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.sweep.shadow_frequency >> self.sweep.shift;
+        let frequency = if self.sweep.negate {
+            self.sweep.negated = true;
+            self.sweep.shadow_frequency - delta
+        } else {
+            self.sweep.shadow_frequency + delta
+        };
+
+        if frequency > MAX_FREQUENCY {
+            self.enabled = false;
+        }
+        frequency
     }
 
-    pub fn volume_env_update(&mut self) {
-        if self.vol_counter.period() == 0 {
+    pub fn clock_sweep(&mut self) {
+        if self.sweep.timer > 0 {
+            self.sweep.timer -= 1;
+        }
+        if self.sweep.timer != 0 {
             return;
         }
 
-        if self.vol_counter.tick() {
-            if self.vol_env_increment && self.vol < 15 {
-                self.vol += 1;
-            } else if self.vol != 0 {
-                self.vol -= 1;
+        self.sweep.timer = reload_period(self.sweep.period);
+        if self.sweep.enabled && self.sweep.period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= MAX_FREQUENCY && self.sweep.shift != 0 {
+                self.sweep.shadow_frequency = frequency;
+                self.frequency = frequency;
+                // The new frequency is checked for overflow straight away,
+                // but not used
+                self.sweep_frequency();
             }
         }
     }
 
-    pub fn sample(&mut self, cpu_cycle: u64) -> f32 {
-        if self.period == 0 || self.frequency > 2048 || !self.is_active() {
-            return 0.;
-        }
-        self.last_cpu_cycle = cpu_cycle;
-        while let Some(_) = self
-            .duty_cycle_step_timer
-            .update(cpu_cycle - self.duty_cycle_step_timer_offset)
-        {
-            self.duty_cycle_step = (self.duty_cycle_step + 1) % 8;
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
         }
+    }
 
-        DUTY_VALUES[self.duty_cycle as usize][self.duty_cycle_step as usize]
-            * (f32::from(self.vol) / 15.0)
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // How long until the output might change
+    pub fn ticks_to_next_step(&self) -> u64 {
+        if self.enabled {
+            self.timer
+        } else {
+            u64::MAX
+        }
     }
 
-    pub fn is_active(&self) -> bool {
-        !self.use_len || self.len > 0
+    pub fn advance(&mut self, ticks: u64) {
+        let period = self.period();
+        let steps = run_timer(&mut self.timer, period, ticks);
+        self.duty_step = ((u64::from(self.duty_step) + steps) % 8) as u8;
     }
 }
@@ -1,17 +1,26 @@
-use j2ds::{next_timer_event, Timer, TimerEvent};
-
 use super::{
-    mixer::Mixer, noise::NoiseChannel, square::SquareChannel, wave::WaveChannel, AudioSink,
+    blip::BlipBuffer, mixer::Mixer, noise::NoiseChannel, square::SquareChannel, wave::WaveChannel,
+    AudioSink,
 };
 use crate::cpu::CLOCK_RATE;
+use crate::system::SystemMode;
+
+// The frame sequencer steps at 512 Hz, clocking the length counters on even
+// steps, the sweep on steps 2 and 6 and the envelopes on step 7
+pub const FRAME_SEQUENCER_PERIOD: u64 = CLOCK_RATE / 512;
+const FRAME_SEQUENCER_STEPS: u8 = 8;
 
 pub struct Synth {
     sink: Box<dyn AudioSink + Send>,
 
-    sample_clock: Timer,
-    len_clock: Timer,
-    env_clock: Timer,
-    freq_clock: Timer,
+    cycle: u64,
+    // Each channel's DAC output, and every change to it
+    levels: [f32; 4],
+    buffers: [BlipBuffer; 4],
+
+    powered: bool,
+    frame_step: u8,
+    next_frame_cycle: u64,
 
     pub mixer: Mixer,
 
@@ -22,16 +31,25 @@ pub struct Synth {
 }
 
 impl Synth {
-    pub fn new(sink: Box<dyn AudioSink + Send>) -> Synth {
+    pub fn new(sink: Box<dyn AudioSink + Send>, mode: SystemMode) -> Synth {
+        let sample_rate = sink.sample_rate();
         Synth {
-            sample_clock: Timer::new(CLOCK_RATE / sink.sample_rate(), 0, 0),
-            len_clock: Timer::new(CLOCK_RATE / 256, 0, 0),
-            env_clock: Timer::new(CLOCK_RATE / 64, 0, 0),
-            freq_clock: Timer::new(CLOCK_RATE / 128, 0, 0),
-
             sink,
 
-            mixer: Mixer::new(),
+            cycle: 0,
+            levels: [0.; 4],
+            buffers: [
+                BlipBuffer::new(sample_rate),
+                BlipBuffer::new(sample_rate),
+                BlipBuffer::new(sample_rate),
+                BlipBuffer::new(sample_rate),
+            ],
+
+            powered: false,
+            frame_step: 0,
+            next_frame_cycle: FRAME_SEQUENCER_PERIOD,
+
+            mixer: Mixer::new(mode, sample_rate),
 
             chan1: SquareChannel::new(),
             chan2: SquareChannel::new(),
@@ -40,42 +58,161 @@ impl Synth {
         }
     }
 
+    pub fn power_on(&mut self) {
+        self.powered = true;
+        self.frame_step = 0;
+    }
+
+    // DMG length counters keep their values through a power cycle
+    pub fn power_off(&mut self, keep_lengths: bool) {
+        self.powered = false;
+        self.chan1.power_off(keep_lengths);
+        self.chan2.power_off(keep_lengths);
+        self.chan3.power_off(keep_lengths);
+        self.chan4.power_off(keep_lengths);
+    }
+
+    // Length counters get clocked early when they are enabled while the next
+    // step of the frame sequencer won't clock them
+    pub fn extra_length_clock(&self) -> bool {
+        self.frame_step % 2 == 1
+    }
+
     pub fn get_next_event_cycle(&self) -> u64 {
-        next_timer_event(&[
-            self.sample_clock,
-            self.len_clock,
-            self.env_clock,
-            self.freq_clock,
-        ])
+        self.next_frame_cycle
     }
 
     pub fn pump_cycle(&mut self, cpu_cycle: u64) {
-        if self.sample_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
+        while self.next_frame_cycle <= cpu_cycle {
+            let next = self.next_frame_cycle;
+            self.advance_to(next);
+
+            self.next_frame_cycle += FRAME_SEQUENCER_PERIOD;
+            if self.powered {
+                self.step_frame_sequencer();
+                self.update_levels();
+            }
+        }
+        self.advance_to(cpu_cycle);
+        self.emit_samples();
+    }
+
+    // Steps through every point a channel's output might change, so each
+    // change lands in the buffers at the cycle it happened
+    fn advance_to(&mut self, cycle: u64) {
+        while self.cycle < cycle {
+            let ticks = [
+                cycle - self.cycle,
+                self.chan1.ticks_to_next_step(),
+                self.chan2.ticks_to_next_step(),
+                self.chan3.ticks_to_next_step(),
+                self.chan4.ticks_to_next_step(),
+            ]
+            .iter()
+            .copied()
+            .min()
+            .unwrap();
+
+            self.chan1.advance(ticks);
+            self.chan2.advance(ticks);
+            self.chan3.advance(ticks);
+            self.chan4.advance(ticks);
+            self.cycle += ticks;
+            self.update_levels();
+        }
+    }
+
+    // Picks up output changes from anything that touched the channels, such
+    // as register writes
+    pub fn update_levels(&mut self) {
+        let levels = [
+            dac_output(self.chan1.dac_enabled(), self.chan1.output()),
+            dac_output(self.chan2.dac_enabled(), self.chan2.output()),
+            dac_output(self.chan3.dac_enabled(), self.chan3.output()),
+            dac_output(self.chan4.dac_enabled(), self.chan4.output()),
+        ];
+
+        for ((level, old), buffer) in levels
+            .iter()
+            .zip(self.levels.iter_mut())
+            .zip(self.buffers.iter_mut())
+        {
+            if level != old {
+                buffer.add_delta(self.cycle, level - *old);
+                *old = *level;
+            }
+        }
+    }
+
+    fn emit_samples(&mut self) {
+        for _ in 0..self.buffers[0].samples_available(self.cycle) {
             let samples = [
-                self.chan1.sample(cpu_cycle),
-                self.chan2.sample(cpu_cycle),
-                self.chan3.sample(cpu_cycle),
-                self.chan4.sample(cpu_cycle),
+                self.buffers[0].read_sample(),
+                self.buffers[1].read_sample(),
+                self.buffers[2].read_sample(),
+                self.buffers[3].read_sample(),
             ];
             self.sink.emit_sample(self.mixer.mix(samples));
             self.sink.emit_raw_chans(samples);
         }
+    }
 
-        if self.len_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
-            self.chan1.decrement_length();
-            self.chan2.decrement_length();
-            self.chan3.decrement_length();
-            self.chan4.decrement_length();
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.chan1.clock_length();
+            self.chan2.clock_length();
+            self.chan3.clock_length();
+            self.chan4.clock_length();
         }
-
-        if self.env_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
-            self.chan1.volume_env_update();
-            self.chan2.volume_env_update();
-            self.chan4.volume_env_update();
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.chan1.clock_sweep();
         }
-
-        if self.freq_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
-            self.chan1.freq_sweep_update();
+        if self.frame_step == 7 {
+            self.chan1.clock_envelope();
+            self.chan2.clock_envelope();
+            self.chan4.clock_envelope();
         }
+        self.frame_step = (self.frame_step + 1) % FRAME_SEQUENCER_STEPS;
+    }
+}
+
+// Runs a channel's frequency timer for some ticks, returning how many times it
+// expired
+pub fn run_timer(timer: &mut u64, period: u64, ticks: u64) -> u64 {
+    if ticks < *timer {
+        *timer -= ticks;
+        0
+    } else {
+        let over = ticks - *timer;
+        *timer = period - over % period;
+        1 + over / period
+    }
+}
+
+// The DACs turn the 0-15 channel outputs into -1 to 1, and put out nothing
+// at all while they're off
+fn dac_output(enabled: bool, v: u8) -> f32 {
+    if enabled {
+        f32::from(v) / 7.5 - 1.
+    } else {
+        0.
     }
 }
+
+#[test]
+fn test_dac_output() {
+    assert_eq!(dac_output(true, 0), -1.);
+    assert_eq!(dac_output(true, 15), 1.);
+    assert_eq!(dac_output(false, 15), 0.);
+}
+
+#[test]
+fn test_run_timer() {
+    let mut timer = 10;
+    assert_eq!(run_timer(&mut timer, 16, 9), 0);
+    assert_eq!(timer, 1);
+    assert_eq!(run_timer(&mut timer, 16, 1), 1);
+    assert_eq!(timer, 16);
+    assert_eq!(run_timer(&mut timer, 16, 40), 2);
+    assert_eq!(timer, 8);
+}
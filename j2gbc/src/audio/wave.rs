@@ -1,72 +1,169 @@
-#[derive(Default)]
+use super::length::LengthCounter;
+use super::synth::run_timer;
+
+const WAVE_RAM_SIZE: usize = 16;
+// How far each NR32 volume setting shifts the samples down
+const VOLUME_SHIFTS: [u8; 4] = [4, 0, 1, 2];
+
 pub struct WaveChannel {
-    samples: [f32; 32],
-    period: u64,
-    pub use_len: bool,
-    len: u8,
+    enabled: bool,
+    dac_enabled: bool,
+    volume: u8,
+    frequency: u16,
+    timer: u64,
 
-    pub enabled: bool,
+    // Which of the 32 samples was read last, and its value
+    position: usize,
+    sample_buffer: u8,
+    ram: [u8; WAVE_RAM_SIZE],
 
-    pub vol_multiplier: f32,
+    pub length: LengthCounter,
+}
 
-    position_offset_cycle: u64,
-    last_cpu_cycle: u64,
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WaveChannel {
     pub fn new() -> WaveChannel {
-        WaveChannel {
-            samples: [-1.; 32],
-            period: 0,
-            use_len: false,
-            len: 0,
-
+        let mut chan = WaveChannel {
             enabled: false,
-            vol_multiplier: 0.,
+            dac_enabled: false,
+            volume: 0,
+            frequency: 0,
+            timer: 0,
+
+            position: 0,
+            sample_buffer: 0,
+            ram: [0; WAVE_RAM_SIZE],
+
+            length: LengthCounter::new(256),
+        };
+        chan.timer = chan.period();
+        chan
+    }
+
+    fn period(&self) -> u64 {
+        (2048 - u64::from(self.frequency)) * 2
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.enabled {
+            self.sample_buffer >> VOLUME_SHIFTS[self.volume as usize]
+        } else {
+            0
+        }
+    }
 
-            position_offset_cycle: 0,
-            last_cpu_cycle: 0,
+    // While the channel plays, wave RAM accesses land on the byte it is
+    // reading from
+    fn ram_index(&self, offset: usize) -> usize {
+        if self.enabled {
+            self.position / 2
+        } else {
+            offset
         }
     }
 
-    pub fn set_frequency_from_bits(&mut self, hi: u8, lo: u8) {
-        let f = (u64::from(hi) & 0b111) << 8 | u64::from(lo);
-        self.period = (2048 - f) * 2 * 32;
+    pub fn read_ram(&self, offset: usize) -> u8 {
+        self.ram[self.ram_index(offset)]
+    }
+
+    pub fn write_ram(&mut self, offset: usize, v: u8) {
+        self.ram[self.ram_index(offset)] = v;
     }
 
-    pub fn set_len(&mut self, len: u8) {
-        self.len = 255 - len;
+    // Wave RAM survives the APU being powered off
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = self.length;
+        let ram = self.ram;
+        *self = WaveChannel::new();
+        self.ram = ram;
+        if keep_length {
+            self.length = length;
+        }
     }
 
-    pub fn decrement_length(&mut self) {
-        if self.len > 0 {
-            self.len -= 1;
+    pub fn set_dac(&mut self, v: u8) {
+        self.dac_enabled = v & 0b1000_0000 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
         }
     }
 
-    pub fn write_sample(&mut self, sample: f32, position: usize) {
-        self.samples[position] = sample;
+    pub fn set_length(&mut self, v: u8) {
+        self.length.load(v);
+    }
+
+    pub fn set_volume(&mut self, v: u8) {
+        self.volume = (v >> 5) & 0b11;
+    }
+
+    pub fn set_frequency_low(&mut self, v: u8) {
+        self.frequency = self.frequency & 0x700 | u16::from(v);
     }
 
-    pub fn sample(&mut self, cpu_cycle: u64) -> f32 {
-        if self.period == 0 || !self.is_active() {
-            return 0.;
+    pub fn set_control(&mut self, v: u8, extra_length_clock: bool) {
+        self.frequency = self.frequency & 0xFF | u16::from(v & 0b111) << 8;
+
+        let trigger = v & 0b1000_0000 != 0;
+        if self
+            .length
+            .set_enabled(v & 0b0100_0000 != 0, extra_length_clock)
+            && !trigger
+        {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger(extra_length_clock);
         }
-        self.last_cpu_cycle = cpu_cycle;
+    }
+
+    fn trigger(&mut self, extra_length_clock: bool) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(extra_length_clock);
+        self.timer = self.period();
+        self.position = 0;
+    }
 
-        let phase = (cpu_cycle + self.position_offset_cycle) % self.period;
-        let position = phase * 32 / self.period;
-        self.samples[position as usize] * self.vol_multiplier
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
     }
 
-    pub fn is_active(&self) -> bool {
-        (!self.use_len || self.len > 0) && self.enabled
+    // How long until the output might change
+    pub fn ticks_to_next_step(&self) -> u64 {
+        if self.enabled {
+            self.timer
+        } else {
+            u64::MAX
+        }
     }
 
-    pub fn reset(&mut self) {
-        self.position_offset_cycle = self.last_cpu_cycle;
-        if self.len == 0 {
-            self.len = 255;
+    pub fn advance(&mut self, ticks: u64) {
+        let period = self.period();
+        let steps = run_timer(&mut self.timer, period, ticks);
+        if steps == 0 || !self.enabled {
+            return;
         }
+
+        self.position = (self.position + steps as usize) % 32;
+        let byte = self.ram[self.position / 2];
+        self.sample_buffer = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0b1111
+        };
     }
 }
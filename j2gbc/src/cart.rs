@@ -1,9 +1,16 @@
 use std::io;
 use std::io::Read;
 
+use crate::mbc::camera::{CameraSource, PocketCamera};
+use crate::mbc::huc1::Huc1;
+use crate::mbc::huc3::Huc3;
 use crate::mbc::mbc0::Mbc0;
 use crate::mbc::mbc1::Mbc1;
+use crate::mbc::mbc2::Mbc2;
+use crate::mbc::mbc3::Mbc3;
 use crate::mbc::mbc5::Mbc5;
+use crate::mbc::mbc7::Mbc7;
+use crate::mbc::rtc::RtcClock;
 use crate::mbc::Mbc;
 use crate::mem::{
     Address, ExtendedAddress, MemDevice, RNG_INTR_TABLE, RNG_ROM_BANK0, RNG_ROM_BANK1,
@@ -29,8 +36,15 @@ impl Cart {
 
         let mbc: Box<dyn Mbc + Send> = match data[OFF_CART_TYPE] {
             0x00 => Box::new(Mbc0::new(data.clone())),
-            0x01 | 0x02 | 0x03 => Box::new(Mbc1::new(data.clone())),
-            0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Box::new(Mbc5::new(data.clone())),
+            0x01..=0x03 => Box::new(Mbc1::new(data.clone(), ram_size(&data))),
+            0x05 | 0x06 => Box::new(Mbc2::new(data.clone())),
+            0x0F | 0x10 => Box::new(Mbc3::new(data.clone(), ram_size(&data), true)),
+            0x11..=0x13 => Box::new(Mbc3::new(data.clone(), ram_size(&data), false)),
+            0x19..=0x1E => Box::new(Mbc5::new(data.clone())),
+            0x22 => Box::new(Mbc7::new(data.clone())),
+            0xFC => Box::new(PocketCamera::new(data.clone(), ram_size(&data))),
+            0xFE => Box::new(Huc3::new(data.clone(), ram_size(&data))),
+            0xFF => Box::new(Huc1::new(data.clone(), ram_size(&data))),
             _ => {
                 unimplemented!("Unsupported MBC {}", data[OFF_CART_TYPE]);
             }
@@ -58,21 +72,14 @@ impl Cart {
     }
 
     pub fn ram_size(&self) -> usize {
-        match self.data[OFF_RAM_SIZE] {
-            0 => 0,
-            1 => 2048,
-            2 => 8192,
-            3 => 32_768,
-            4 => 131_072,
-            _ => unimplemented!(),
-        }
+        ram_size(&self.data)
     }
 
     pub fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
         if a.in_(RNG_ROM_BANK1) {
             self.mbc.map_address_into_rom(a)
         } else {
-            ExtendedAddress(u32::from(a.0))
+            self.mbc.map_address_into_rom_bank0(a)
         }
     }
 
@@ -84,6 +91,22 @@ impl Cart {
         self.mbc.set_sram(buf);
     }
 
+    pub fn pump_cycle(&mut self, cycle: u64) {
+        self.mbc.pump_cycle(cycle);
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mbc.set_rtc_clock(clock);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource + Send>) {
+        self.mbc.set_camera_source(source);
+    }
+
     pub fn get_mmu_exceptions(&self) -> MmuExceptions {
         MmuExceptions::from_title(self.name().as_str())
     }
@@ -94,10 +117,22 @@ impl Cart {
     }
 }
 
+fn ram_size(data: &[u8]) -> usize {
+    match data[OFF_RAM_SIZE] {
+        0 => 0,
+        1 => 2048,
+        2 => 8192,
+        3 => 32_768,
+        4 => 131_072,
+        5 => 65_536,
+        _ => unimplemented!(),
+    }
+}
+
 impl MemDevice for Cart {
     fn read(&self, a: Address) -> Result<u8, ()> {
         if a.in_(RNG_ROM_BANK0) || a.in_(RNG_INTR_TABLE) {
-            Ok(self.data[a.0 as usize])
+            Ok(self.data[self.mbc.map_address_into_rom_bank0(a).0 as usize])
         } else {
             self.mbc.read(a)
         }
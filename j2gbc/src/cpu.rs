@@ -13,12 +13,15 @@ use crate::{
     audio::AudioSink,
     cart::Cart,
     inst::{Arith, Bits, Control, Instruction, Load, Logic},
-    mem::{Address, MemDevice},
-    mmu::Mmu,
+    mem::{Address, MemDevice, REG_DIV},
+    mmu::{Mmu, CGB_BOOT_ROM_SIZE},
 };
 
 pub const CLOCK_RATE: u64 = 4_194_304;
 pub const LONGEST_INSTRUCTION_CYCLE: u64 = 20; // LD (a16),SP
+const INTERRUPT_MASK: u8 = 0b0001_1111;
+// Roughly 2050 M-cycles
+const SPEED_SWITCH_CYCLES: u64 = 8200;
 
 mod interrupt;
 mod register;
@@ -36,8 +39,23 @@ pub struct Cpu {
     pub sp: Address,
     pub mmu: Mmu,
     cycle: u64,
+    // M-cycles the current instruction has taken so far
+    instruction_m_cycles: u64,
     pub interrupt_master_enable: bool,
+    // EI only takes effect after the instruction following it
+    interrupt_master_enable_pending: bool,
     halted: bool,
+    // HALT with IME off and an interrupt already pending doesn't halt, but
+    // fails to step PC past the next opcode
+    halt_bug: bool,
+    // Left for good by an illegal opcode. Nothing but a reset gets the CPU
+    // going again, but the rest of the system keeps running.
+    locked: bool,
+    // In STOP's low-power mode, where the whole system is paused until a
+    // button is pressed
+    stopped: bool,
+    // Time spent in STOP, which the LCD, timer and APU never see
+    stopped_cycles: u64,
 
     pub debug_halted: bool,
     pub breakpoints: HashSet<Address>,
@@ -45,12 +63,23 @@ pub struct Cpu {
 }
 
 impl Cpu {
-    pub fn new(c: Cart, audio_sink: Box<dyn AudioSink + Send>, mut cgb_mode: bool) -> Cpu {
+    pub fn new(
+        c: Cart,
+        audio_sink: Box<dyn AudioSink + Send>,
+        mut cgb_mode: bool,
+        boot_rom: Option<Vec<u8>>,
+    ) -> Cpu {
         let initial_breakpoints = HashSet::new();
 
         if cgb_mode {
-            cgb_mode = c.supports_cgb_mode();
+            // The CGB boot ROM is what puts the hardware into DMG mode for
+            // carts that need it, so with one we start out as a CGB either way
+            cgb_mode = match boot_rom {
+                Some(ref rom) => rom.len() == CGB_BOOT_ROM_SIZE,
+                None => c.supports_cgb_mode(),
+            };
         }
+        let has_boot_rom = boot_rom.is_some();
 
         debug!("CGB mode: {}", cgb_mode);
 
@@ -58,16 +87,31 @@ impl Cpu {
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
             sp: Address(0xFFFE),
             pc: Address(0x100),
-            mmu: Mmu::new(c, audio_sink, cgb_mode),
+            mmu: Mmu::new(c, audio_sink, cgb_mode, boot_rom),
             cycle: 0,
+            instruction_m_cycles: 0,
             interrupt_master_enable: false,
+            interrupt_master_enable_pending: false,
             halted: false,
+            halt_bug: false,
+            locked: false,
+            stopped: false,
+            stopped_cycles: 0,
 
             debug_halted: false,
             breakpoints: initial_breakpoints,
             interrupt_breakpoints: HashSet::new(),
         };
 
+        if has_boot_rom {
+            // Start from reset and let the boot ROM do the rest
+            cpu.pc = Address(0x0000);
+            cpu.sp = Address(0x0000);
+            cpu.mmu.lcd.reset();
+            cpu.mmu.audio.reset();
+            return cpu;
+        }
+
         cpu[Register8::A] = if cgb_mode { 0x11 } else { 0x01 };
         cpu[Register8::F] = 0xB0;
         cpu[Register8::B] = 0x00;
@@ -84,26 +128,34 @@ impl Cpu {
         self.cycle
     }
 
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
     fn execute(&mut self, i: Instruction) -> Result<(), ()> {
         let mut branch_taken = false;
         match i {
             Instruction::Nop => {}
             Instruction::EnableInterrupts => {
-                self.interrupt_master_enable = true;
+                self.interrupt_master_enable_pending = true;
             }
             Instruction::DisableInterrupts => {
                 self.interrupt_master_enable = false;
+                self.interrupt_master_enable_pending = false;
             }
             Instruction::Stop => {
-                if self.mmu.prepared_speed_switch {
-                    self.mmu.toggle_double_speed();
-                } else {
-                    error!("Stop executed without speed switch mode prepared");
-                    return Err(());
-                }
+                self.execute_stop()?;
+            }
+            Instruction::Illegal(op) => {
+                error!("Illegal instruction {:#X} at {}, locking up", op, self.pc);
+                self.locked = true;
             }
             Instruction::Halt => {
-                self.halted = true;
+                if self.interrupt_master_enable || self.pending_interrupts() == 0 {
+                    self.halted = true;
+                } else {
+                    self.halt_bug = true;
+                }
             }
             Instruction::SetCarry => {
                 let mut f = self.flags();
@@ -140,15 +192,52 @@ impl Cpu {
                 self.execute_logic(l)?;
             }
         }
-        let cycle_cost = if self.mmu.double_speed_mode {
-            i.cycles(branch_taken) / 2
-        } else {
-            i.cycles(branch_taken)
-        };
-        self.cycle += u64::from(cycle_cost);
+
+        // Whatever is left is internal to the CPU
+        let m_cycles = u64::from(i.cycles(branch_taken)) / 4;
+        debug_assert!(
+            self.instruction_m_cycles <= m_cycles,
+            "{} took too many cycles",
+            i
+        );
+        while self.instruction_m_cycles < m_cycles {
+            self.tick()?;
+        }
+        self.instruction_m_cycles = 0;
         Ok(())
     }
 
+    // What STOP does depends on the joypad, a pending speed switch and
+    // pending interrupts. When it turns out to be a 1-byte opcode the byte
+    // after it runs next.
+    fn execute_stop(&mut self) -> Result<(), ()> {
+        let interrupt_pending = self.pending_interrupts() != 0;
+
+        if self.mmu.input.is_selected_button_pressed() {
+            if interrupt_pending {
+                self.pc = Address(self.pc.0.wrapping_sub(1));
+            } else {
+                self.halted = true;
+            }
+            return Ok(());
+        }
+
+        self.mmu.timer.write(REG_DIV, 0)?;
+        if interrupt_pending {
+            self.pc = Address(self.pc.0.wrapping_sub(1));
+        }
+
+        if self.mmu.prepared_speed_switch {
+            self.mmu.toggle_double_speed();
+            // The CPU sits out while the clock settles
+            self.stall(SPEED_SWITCH_CYCLES)
+        } else {
+            self.stopped = true;
+            self.mmu.lcd.blank();
+            Ok(())
+        }
+    }
+
     fn execute_arith(&mut self, a: Arith) -> Result<(), ()> {
         match a {
             Arith::Add(o) => {
@@ -342,20 +431,19 @@ impl Cpu {
                 *branch_taken = true;
             }
             Control::Return => {
-                self.pc = Address(self.mmu.read16(self.sp)?);
-                self.sp += Address(2);
+                self.pc = Address(self.pop16()?);
                 *branch_taken = true;
             }
             Control::InterruptReturn => {
-                self.pc = Address(self.mmu.read16(self.sp)?);
-                self.sp += Address(2);
+                self.pc = Address(self.pop16()?);
                 self.interrupt_master_enable = true;
                 *branch_taken = true;
             }
             Control::ReturnConditional(cond) => {
+                // The condition takes an M-cycle to check
+                self.tick()?;
                 if self.flags().matches(cond) {
-                    self.pc = Address(self.mmu.read16(self.sp)?);
-                    self.sp += Address(2);
+                    self.pc = Address(self.pop16()?);
                     *branch_taken = true;
                 }
             }
@@ -376,6 +464,7 @@ impl Cpu {
             }
             Control::Call(a) | Control::Reset(a) => {
                 let v = self.pc.into();
+                self.tick()?;
                 self.push16(v)?;
                 self.pc = a;
                 *branch_taken = true;
@@ -383,6 +472,7 @@ impl Cpu {
             Control::CallConditional(a, cond) => {
                 if self.flags().matches(cond) {
                     let v = self.pc.into();
+                    self.tick()?;
                     self.push16(v)?;
                     self.pc = a;
                     *branch_taken = true;
@@ -402,22 +492,22 @@ impl Cpu {
             Load::LoadIndirectFromA(d) => {
                 let a = self.read_r16(Register16::HL);
                 let v = self[Register8::A];
-                self.mmu.write(Address(a), v)?;
+                self.write_mem(Address(a), v)?;
                 self.write_r16(Register16::HL, (Wrapping(a) + Wrapping(d as u16)).0);
             }
             Load::LoadAFromIndirect(d) => {
                 let a = self.read_r16(Register16::HL);
-                self[Register8::A] = self.mmu.read(Address(a))?;
+                self[Register8::A] = self.read_mem(Address(a))?;
                 self.write_r16(Register16::HL, (Wrapping(a) + Wrapping(d as u16)).0);
             }
             Load::LoadIndirectHiFromA => {
                 let a = Address(u16::from(self[Register8::C]) + 0xFF00);
                 let v = self[Register8::A];
-                self.mmu.write(a, v)?;
+                self.write_mem(a, v)?;
             }
             Load::LoadAFromIndirectHi => {
                 let a = Address(u16::from(self[Register8::C]) + 0xFF00);
-                let v = self.mmu.read(a)?;
+                let v = self.read_mem(a)?;
                 self[Register8::A] = v;
             }
             Load::LoadHLFromSP(v) => {
@@ -439,7 +529,8 @@ impl Cpu {
             }
             Load::LoadMemoryFromSP(a) => {
                 let v = self.read_r16(Register16::SP);
-                self.mmu.write16(a, v)?;
+                self.write_mem(a, lo(v))?;
+                self.write_mem(a + Address(1), hi(v))?;
             }
             Load::LoadIndirectRegisterFromA(r) => {
                 let v = self[Register8::A];
@@ -454,10 +545,10 @@ impl Cpu {
             }
             Load::LoadMemoryFromA(a) => {
                 let v = self[Register8::A];
-                self.mmu.write(a, v)?;
+                self.write_mem(a, v)?;
             }
             Load::LoadAFromMemory(a) => {
-                let v = self.mmu.read(a)?;
+                let v = self.read_mem(a)?;
                 self[Register8::A] = v;
             }
             Load::Pop(r) => {
@@ -466,6 +557,7 @@ impl Cpu {
             }
             Load::Push(r) => {
                 let v = self.read_r16(r);
+                self.tick()?;
                 self.push16(v)?;
             }
         }
@@ -528,12 +620,12 @@ impl Cpu {
         Ok(())
     }
 
-    fn read_operand(&self, o: Operand) -> Result<u8, ()> {
+    fn read_operand(&mut self, o: Operand) -> Result<u8, ()> {
         match o {
             Operand::Immediate(v) => Ok(v),
             Operand::Register(r) => Ok(self[r]),
             Operand::IndirectRegister(ir) => self.read_indirect(ir),
-            Operand::IndirectAddress(a) => self.mmu.read(a),
+            Operand::IndirectAddress(a) => self.read_mem(a),
         }
     }
 
@@ -544,51 +636,87 @@ impl Cpu {
                 self[r] = v;
                 Ok(())
             }
-            Operand::IndirectAddress(a) => self.mmu.write(a, v),
+            Operand::IndirectAddress(a) => self.write_mem(a, v),
             Operand::IndirectRegister(r) => self.write_indirect(r, v),
         }
     }
 
     pub fn run_cycle(&mut self) -> Result<(), ()> {
+        if self.locked || self.stopped {
+            return Ok(());
+        }
+
+        // A pending interrupt ends HALT even if IME is off and it can't be
+        // dispatched
+        if self.halted && self.pending_interrupts() != 0 {
+            self.halted = false;
+        }
+
         self.fire_interrupts()?;
 
         if self.halted {
             return Ok(());
         }
 
+        if self.interrupt_master_enable_pending {
+            self.interrupt_master_enable_pending = false;
+            self.interrupt_master_enable = true;
+        }
+
         if self.breakpoints.contains(&self.pc) {
             self.breakpoints.remove(&self.pc);
             error!("Breakpoint");
             return Err(());
         }
 
-        let (instruction, len) = self.fetch_instruction(self.pc)?;
+        let halt_bug = self.halt_bug;
+        self.halt_bug = false;
+        let (instruction, len) = if halt_bug {
+            // The opcode is read again as the first byte after it
+            self.fetch_instruction_bytes([self.pc, self.pc, self.pc + Address(1)])?
+        } else {
+            self.fetch_instruction(self.pc)?
+        };
 
-        self.pc += Address(u16::from(len));
-        self.execute(instruction)?;
+        // Each byte of the instruction takes an M-cycle to fetch, except for
+        // STOP's second one which is skipped
+        let fetch_m_cycles = match instruction {
+            Instruction::Stop => 1,
+            _ => len,
+        };
+        for _ in 0..fetch_m_cycles {
+            self.tick()?;
+        }
 
-        self.drive_peripherals()
+        if halt_bug {
+            self.pc += Address(u16::from(len) - 1);
+        } else {
+            self.pc += Address(u16::from(len));
+        }
+        self.execute(instruction)
     }
 
     pub fn run_for_duration(&mut self, duration: &Duration) {
         let cycles_to_run = duration_to_cycle_count(&duration);
-        let stop_at_cycle = self.cycle() + cycles_to_run;
-        self.mmu
-            .lcd
-            .set_running_until(stop_at_cycle + LONGEST_INSTRUCTION_CYCLE);
+        self.run_until_cycle(self.cycle() + cycles_to_run);
+    }
+
+    pub fn run_until_cycle(&mut self, stop_at_cycle: u64) {
+        self.mmu.lcd.set_running_until(
+            (stop_at_cycle + LONGEST_INSTRUCTION_CYCLE).saturating_sub(self.stopped_cycles),
+        );
         while self.cycle() < stop_at_cycle && !self.debug_halted {
+            if self.stopped {
+                self.idle_stopped(stop_at_cycle);
+                continue;
+            }
+
             if self.run_cycle().is_err() {
                 self.debug_halted = true;
             }
 
-            if self.halted {
-                self.cycle = min(
-                    self.mmu.audio.synth.get_next_event_cycle(),
-                    min(
-                        self.mmu.lcd.get_next_event_cycle(),
-                        min(self.mmu.timer.get_next_event_cycle(), stop_at_cycle),
-                    ),
-                );
+            if self.halted || self.locked {
+                self.cycle = self.next_event_cycle(stop_at_cycle);
                 if self.drive_peripherals().is_err() {
                     self.debug_halted = true;
                 }
@@ -596,13 +724,101 @@ impl Cpu {
         }
     }
 
+    // Only the cartridge and the link cable keep time in STOP. Everything
+    // else picks up where it left off once a button wakes the CPU.
+    fn idle_stopped(&mut self, until: u64) {
+        self.stopped_cycles += until - self.cycle;
+        self.cycle = until;
+        self.mmu.cart.pump_cycle(self.cycle);
+        let inters = self.mmu.serial.pump_cycle(self.cycle);
+        self.request_interrupts(inters);
+    }
+
+    // The cycle as seen by the parts of the system that stand still in STOP
+    fn peripheral_cycle(&self) -> u64 {
+        self.cycle - self.stopped_cycles
+    }
+
+    fn next_event_cycle(&self, limit: u64) -> u64 {
+        let held = min(
+            self.mmu.audio.synth.get_next_event_cycle(),
+            min(
+                self.mmu.lcd.get_next_event_cycle(),
+                self.mmu.timer.get_next_event_cycle(),
+            ),
+        );
+        min(
+            held.saturating_add(self.stopped_cycles),
+            min(self.mmu.serial.get_next_event_cycle(), limit),
+        )
+    }
+
+    fn m_cycle(&self) -> u64 {
+        if self.mmu.double_speed_mode {
+            2
+        } else {
+            4
+        }
+    }
+
+    // Lets the rest of the system run for one M-cycle
+    fn tick(&mut self) -> Result<(), ()> {
+        self.cycle += self.m_cycle();
+        self.instruction_m_cycles += 1;
+        self.drive_peripherals()
+    }
+
+    // The CPU's own memory accesses each take an M-cycle, after which the
+    // rest of the system catches up
+    fn read_mem(&mut self, a: Address) -> Result<u8, ()> {
+        let v = self.mmu.read(a)?;
+        self.tick()?;
+        Ok(v)
+    }
+
+    fn write_mem(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        self.mmu.write(a, v)?;
+        self.tick()
+    }
+
     fn drive_peripherals(&mut self) -> Result<(), ()> {
-        self.mmu.audio.synth.pump_cycle(self.cycle);
+        self.pump_peripherals()?;
+
+        // DMA keeps the CPU off the bus, but everything else carries on
+        loop {
+            let stall = self.mmu.take_stall_cycles();
+            if stall == 0 {
+                return Ok(());
+            }
+            self.stall(stall)?;
+        }
+    }
 
-        let i1 = self.mmu.lcd.pump_cycle(self.cycle);
-        let i2 = self.mmu.timer.pump_cycle(self.cycle);
+    fn stall(&mut self, cycles: u64) -> Result<(), ()> {
+        let stall_end = self.cycle + cycles;
+        while self.cycle < stall_end {
+            self.cycle = self.next_event_cycle(stall_end);
+            self.pump_peripherals()?;
+        }
+        Ok(())
+    }
 
-        self.request_interrupts(i1.merge(i2));
+    fn pump_peripherals(&mut self) -> Result<(), ()> {
+        let held = self.peripheral_cycle();
+        self.mmu.audio.synth.pump_cycle(held);
+        self.mmu.cart.pump_cycle(self.cycle);
+        self.mmu.pump_oam_dma(self.cycle)?;
+
+        let i1 = self.mmu.lcd.pump_cycle(held);
+        let i2 = self.mmu.timer.pump_cycle(held);
+        let i3 = self.mmu.serial.pump_cycle(self.cycle);
+
+        self.request_interrupts(i1.merge(i2).merge(i3));
+
+        // HBlank HDMA is paused while the CPU is halted
+        if self.mmu.lcd.take_hblank_start() && !self.halted {
+            self.mmu.hblank_hdma()?;
+        }
 
         Ok(())
     }
@@ -614,39 +830,62 @@ impl Cpu {
         }
     }
 
+    fn pending_interrupts(&self) -> u8 {
+        self.mmu.interrupt_flag & self.mmu.interrupt_enable & INTERRUPT_MASK
+    }
+
     fn fire_interrupts(&mut self) -> Result<(), ()> {
-        if self.interrupt_master_enable {
-            if let (Some(int), if_) =
-                Interrupt::int_to_run(self.mmu.interrupt_flag, self.mmu.interrupt_enable)
-            {
-                self.mmu.interrupt_flag = if_;
-                self.fire_interrupt(int)?;
+        if self.interrupt_master_enable && self.pending_interrupts() != 0 {
+            self.fire_interrupt()?;
+        }
+
+        Ok(())
+    }
+
+    // Dispatch takes 5 M-cycles. Which interrupt wins is only decided after
+    // the high byte of PC is pushed, so pushing over IE can redirect it or
+    // cancel it, sending the CPU to 0x0000 instead.
+    fn fire_interrupt(&mut self) -> Result<(), ()> {
+        self.interrupt_master_enable = false;
+        self.tick()?;
+        self.tick()?;
+
+        let pc: u16 = self.pc.into();
+        self.sp = Address(self.sp.0.wrapping_sub(1));
+        self.write_mem(self.sp, hi(pc))?;
+
+        let (int, if_) = Interrupt::int_to_run(self.mmu.interrupt_flag, self.mmu.interrupt_enable);
+        self.mmu.interrupt_flag = if_;
+
+        self.sp = Address(self.sp.0.wrapping_sub(1));
+        self.write_mem(self.sp, lo(pc))?;
+
+        self.pc = match int {
+            Some(int) => {
                 if self.interrupt_breakpoints.contains(&int) {
                     self.interrupt_breakpoints.remove(&int);
                     debug!("Interrupt breakpoint {:?}", int);
                     self.debug_halted = true;
                 }
+                int.table_address()
             }
-        }
+            None => Address(0x0000),
+        };
+        self.tick()?;
+        self.instruction_m_cycles = 0;
 
         Ok(())
     }
 
-    fn fire_interrupt(&mut self, int: Interrupt) -> Result<(), ()> {
-        let v = self.pc.into();
-        self.push16(v)?;
-
-        self.pc = int.table_address();
-        self.interrupt_master_enable = false;
-
-        Ok(())
+    pub fn fetch_instruction(&self, address: Address) -> Result<(Instruction, u8), ()> {
+        self.fetch_instruction_bytes([address, address + Address(1), address + Address(2)])
     }
 
-    pub fn fetch_instruction(&self, address: Address) -> Result<(Instruction, u8), ()> {
+    fn fetch_instruction_bytes(&self, addresses: [Address; 3]) -> Result<(Instruction, u8), ()> {
         let bytes = [
-            self.mmu.read(address)?,
-            self.mmu.read(address + Address(1))?,
-            self.mmu.read(address + Address(2))?,
+            self.mmu.read(addresses[0])?,
+            self.mmu.read(addresses[1])?,
+            self.mmu.read(addresses[2])?,
         ];
         Instruction::decode(bytes)
     }
@@ -686,26 +925,28 @@ impl Cpu {
     }
 
     fn push16(&mut self, v: u16) -> Result<(), ()> {
-        let nsp = self.sp - Address(2);
-        self.mmu.write16(nsp, v)?;
-        self.sp = nsp;
-        Ok(())
+        self.sp = Address(self.sp.0.wrapping_sub(1));
+        self.write_mem(self.sp, hi(v))?;
+        self.sp = Address(self.sp.0.wrapping_sub(1));
+        self.write_mem(self.sp, lo(v))
     }
 
     fn pop16(&mut self) -> Result<u16, ()> {
-        let v = self.mmu.read16(self.sp)?;
-        self.sp += Address(2);
-        Ok(v)
+        let lo = self.read_mem(self.sp)?;
+        self.sp = Address(self.sp.0.wrapping_add(1));
+        let hi = self.read_mem(self.sp)?;
+        self.sp = Address(self.sp.0.wrapping_add(1));
+        Ok(hi_lo(hi, lo))
     }
 
-    fn read_indirect(&self, r: Register16) -> Result<u8, ()> {
+    fn read_indirect(&mut self, r: Register16) -> Result<u8, ()> {
         let a = Address(self.read_r16(r));
-        self.mmu.read(a)
+        self.read_mem(a)
     }
 
     fn write_indirect(&mut self, r: Register16, v: u8) -> Result<(), ()> {
         let a = Address(self.read_r16(r));
-        self.mmu.write(a, v)
+        self.write_mem(a, v)
     }
 
     fn flags(&self) -> Flags {
@@ -714,6 +955,10 @@ impl Cpu {
 
     pub fn request_p1_int(&mut self) {
         self.mmu.interrupt_flag |= Interrupt::Controller.bits();
+        // A joypad line going low is the only way out of STOP
+        if self.mmu.input.is_selected_button_pressed() {
+            self.stopped = false;
+        }
     }
 }
 
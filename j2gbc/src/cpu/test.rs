@@ -1,10 +1,13 @@
 use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
 
-use super::{Arith, Cpu, Instruction, Load, Operand, Register16, Register8};
+use super::{
+    Arith, ConditionCode, Control, Cpu, Instruction, Load, Operand, Register16, Register8,
+};
 use crate::alu::Flags;
 use crate::audio::NullSink;
 use crate::cart::Cart;
+use crate::input::Button;
 use crate::mem::{Address, MemDevice};
 
 const INTIAL_PC: Address = Address(0x0150);
@@ -29,7 +32,8 @@ fn test_ei() {
 
     let i = Instruction::EnableInterrupts;
     cpu.execute(i).unwrap();
-    assert_eq!(cpu.interrupt_master_enable, true);
+    assert!(!cpu.interrupt_master_enable);
+    assert!(cpu.interrupt_master_enable_pending);
 
     assert_reg_vals(&cpu, &[]);
     assert_eq!(cpu.pc, INTIAL_PC);
@@ -43,7 +47,7 @@ fn test_di() {
 
     let i = Instruction::DisableInterrupts;
     cpu.execute(i).unwrap();
-    assert_eq!(cpu.interrupt_master_enable, false);
+    assert!(!cpu.interrupt_master_enable);
 
     assert_reg_vals(&cpu, &[]);
     assert_eq!(cpu.pc, INTIAL_PC);
@@ -57,7 +61,7 @@ fn test_halt() {
 
     let i = Instruction::Halt;
     cpu.execute(i).unwrap();
-    assert_eq!(cpu.halted, true);
+    assert!(cpu.halted);
 
     assert_reg_vals(&cpu, &[]);
     assert_eq!(cpu.pc, INTIAL_PC);
@@ -216,13 +220,285 @@ fn test_load_indirect_decrement() {
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_gdma_stall() {
+    let mut cpu = make_test_cpu_in_mode(true);
+    cpu.mmu.write(Address(0xFF51), 0xC0).unwrap();
+    cpu.mmu.write(Address(0xFF52), 0x00).unwrap();
+    cpu.mmu.write(Address(0xFF53), 0x00).unwrap();
+    cpu.mmu.write(Address(0xFF54), 0x00).unwrap();
+    let ly = cpu.mmu.read(Address(0xFF44)).unwrap();
+
+    // 128 blocks of 32 cycles, long enough for several scanlines
+    cpu.mmu.write(Address(0xFF55), 0x7F).unwrap();
+    cpu.drive_peripherals().unwrap();
+
+    assert_eq!(cpu.cycle(), 128 * 32);
+    assert!(cpu.mmu.read(Address(0xFF44)).unwrap() >= ly + 8);
+}
+
+#[test]
+fn test_accesses_are_timed_separately() {
+    let mut cpu = make_test_cpu();
+    cpu.mmu.write(Address(0xC09F), 0x5A).unwrap();
+    cpu.mmu.write(Address(0xFF46), 0xC0).unwrap();
+    cpu.drive_peripherals().unwrap();
+
+    // OAM DMA finishes between the two reads of the pop
+    cpu.cycle = 636;
+    cpu.drive_peripherals().unwrap();
+    cpu.sp = Address(0xFE9E);
+    cpu.execute(Instruction::Load(Load::Pop(Register16::BC)))
+        .unwrap();
+
+    assert_eq!(cpu[Register8::C], 0xFF);
+    assert_eq!(cpu[Register8::B], 0x5A);
+    assert_eq!(cpu.cycle, 636 + 12);
+}
+
+#[test]
+fn test_ei_delay() {
+    // ei; nop; nop
+    let mut cpu = make_program_test_cpu(&[0xFB, 0x00, 0x00]);
+    cpu.mmu.interrupt_enable = 0b1;
+    cpu.mmu.interrupt_flag = 0b1;
+
+    cpu.run_cycle().unwrap();
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pc, Address(0xC102));
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pc, Address(0x0041));
+}
+
+#[test]
+fn test_ei_di() {
+    // ei; di; nop
+    let mut cpu = make_program_test_cpu(&[0xFB, 0xF3, 0x00]);
+    cpu.mmu.interrupt_enable = 0b1;
+    cpu.mmu.interrupt_flag = 0b1;
+
+    for _ in 0..3 {
+        cpu.run_cycle().unwrap();
+    }
+    assert_eq!(cpu.pc, Address(0xC103));
+    assert!(!cpu.interrupt_master_enable);
+}
+
+#[test]
+fn test_halt_wakes_without_ime() {
+    // halt; inc a
+    let mut cpu = make_program_test_cpu(&[0x76, 0x3C]);
+    cpu.mmu.interrupt_enable = 0b100;
+
+    cpu.run_cycle().unwrap();
+    cpu.run_cycle().unwrap();
+    assert!(cpu.halted);
+    assert_eq!(cpu.pc, Address(0xC101));
+
+    cpu.mmu.interrupt_flag = 0b100;
+    cpu.run_cycle().unwrap();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, Address(0xC102));
+    assert_eq!(cpu.mmu.interrupt_flag & 0b100, 0b100);
+}
+
+#[test]
+fn test_halt_bug() {
+    // halt; inc a; ld b, $04
+    let mut cpu = make_program_test_cpu(&[0x76, 0x3C, 0x06, 0x04]);
+    cpu.mmu.interrupt_enable = 0b100;
+    cpu.mmu.interrupt_flag = 0b100;
+    cpu[Register8::A] = 0;
+
+    for _ in 0..4 {
+        cpu.run_cycle().unwrap();
+    }
+    assert_eq!(cpu[Register8::A], 2);
+    assert_eq!(cpu[Register8::B], 4);
+    assert_eq!(cpu.pc, Address(0xC104));
+}
+
+#[test]
+fn test_dispatch() {
+    let mut cpu = make_program_test_cpu(&[0x00]);
+    cpu.interrupt_master_enable = true;
+    cpu.mmu.interrupt_enable = 0b101;
+    cpu.mmu.interrupt_flag = 0b100;
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pc, Address(0x0051));
+    assert_eq!(cpu.sp, Address(0xCFFE));
+    assert_eq!(cpu.mmu.read(Address(0xCFFF)), Ok(0xC1));
+    assert_eq!(cpu.mmu.read(Address(0xCFFE)), Ok(0x00));
+    assert_eq!(cpu.mmu.interrupt_flag & 0b100, 0);
+    // Five M-cycles of dispatch, then the first instruction of the handler
+    assert_eq!(cpu.cycle(), 5 * 4 + 4);
+}
+
+#[test]
+fn test_dispatch_cancelled_by_ie_push() {
+    let mut cpu = make_program_test_cpu(&[0x00]);
+    cpu.interrupt_master_enable = true;
+    cpu.mmu.interrupt_enable = 0b1;
+    cpu.mmu.interrupt_flag = 0b1;
+    // The high byte of PC lands on IE, which turns off VBlank
+    cpu.pc = Address(0xC200);
+    cpu.sp = Address(0x0000);
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.mmu.interrupt_enable, 0xC2);
+    assert_eq!(cpu.mmu.interrupt_flag & 0b1, 0b1);
+    assert_eq!(cpu.pc, Address(0x0001));
+}
+
+#[test]
+fn test_illegal_opcode_locks_up() {
+    let mut cpu = make_program_test_cpu(&[0xD3, 0x00]);
+    cpu.interrupt_master_enable = true;
+    cpu.mmu.interrupt_enable = 0b1;
+
+    cpu.run_until_cycle(70224 * 2);
+    assert!(cpu.is_locked());
+    assert!(!cpu.debug_halted);
+    assert_eq!(cpu.pc, Address(0xC101));
+    assert!(cpu.cycle() >= 70224 * 2);
+    // VBlank still happens, but is never serviced
+    assert_eq!(cpu.mmu.interrupt_flag & 0b1, 0b1);
+}
+
+#[test]
+fn test_stop_until_button_press() {
+    // stop; inc a
+    let mut cpu = make_program_test_cpu(&[0x10, 0x00, 0x3C]);
+    cpu[Register8::A] = 0;
+    cpu.mmu.write(Address(0xFF00), 0x10).unwrap();
+
+    // Time keeps passing, but the timer and LCD stand still
+    cpu.run_until_cycle(70224);
+    assert!(cpu.stopped);
+    assert_eq!(cpu.cycle(), 70224);
+    assert_eq!(cpu.pc, Address(0xC102));
+    assert_eq!(cpu.mmu.read(Address(0xFF04)), Ok(0));
+    assert_eq!(cpu.mmu.read(Address(0xFF44)), Ok(0));
+
+    // Only a button P1 is selecting wakes it up
+    cpu.mmu.input.activate_button(Button::Up);
+    cpu.request_p1_int();
+    assert!(cpu.stopped);
+    cpu.mmu.input.activate_button(Button::Start);
+    cpu.request_p1_int();
+    assert!(!cpu.stopped);
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu[Register8::A], 1);
+}
+
+#[test]
+fn test_stop_with_interrupt_pending() {
+    // stop; inc a
+    let mut cpu = make_program_test_cpu(&[0x10, 0x3C]);
+    cpu[Register8::A] = 0;
+    cpu.mmu.interrupt_enable = 0b100;
+    cpu.mmu.interrupt_flag = 0b100;
+
+    cpu.run_cycle().unwrap();
+    assert!(cpu.stopped);
+    assert_eq!(cpu.pc, Address(0xC101));
+}
+
+#[test]
+fn test_stop_with_button_held() {
+    let mut cpu = make_program_test_cpu(&[0x10, 0x00]);
+    cpu.mmu.write(Address(0xFF00), 0x10).unwrap();
+    cpu.mmu.input.activate_button(Button::A);
+
+    cpu.run_cycle().unwrap();
+    assert!(!cpu.stopped);
+    assert!(cpu.halted);
+}
+
+#[test]
+fn test_stop_speed_switch() {
+    let mut cpu = make_test_cpu_in_mode(true);
+    cpu.pc = Address(0xC100);
+    cpu.mmu.write(Address(0xC100), 0x10).unwrap();
+    cpu.mmu.write(Address(0xFF4D), 0x01).unwrap();
+
+    cpu.run_cycle().unwrap();
+    assert!(!cpu.stopped);
+    assert!(cpu.mmu.double_speed_mode);
+    assert_eq!(cpu.mmu.read(Address(0xFF4D)), Ok(0xFE));
+    assert!(cpu.cycle() >= 8200);
+}
+
+#[test]
+fn test_instruction_timing() {
+    // With no flags set nz and nc branches are taken, with all flags set z and c are
+    for flags in &[0x00, 0xF0] {
+        for cb in &[false, true] {
+            for op in 0..=0xFF {
+                let bytes = if *cb {
+                    [0xCB, op, 0x00]
+                } else {
+                    [op, 0x00, 0xC0]
+                };
+                let (i, _) = match Instruction::decode(bytes) {
+                    Ok(decoded) => decoded,
+                    Err(()) => continue,
+                };
+                if let Instruction::Stop = i {
+                    continue;
+                }
+
+                let taken = match i {
+                    Instruction::Control(Control::JumpConditional(_, cond))
+                    | Instruction::Control(Control::CallConditional(_, cond))
+                    | Instruction::Control(Control::JumpRelativeConditional(_, cond))
+                    | Instruction::Control(Control::ReturnConditional(cond)) => match cond {
+                        ConditionCode::NotZero | ConditionCode::NotCarry => *flags == 0x00,
+                        ConditionCode::Zero | ConditionCode::Carry => *flags == 0xF0,
+                    },
+                    _ => true,
+                };
+
+                let mut cpu = make_test_cpu();
+                cpu.write_r16(Register16::BC, 0xC010);
+                cpu.write_r16(Register16::DE, 0xC020);
+                cpu.write_r16(Register16::HL, 0xC030);
+                cpu[Register8::F] = *flags;
+                cpu.sp = Address(0xD000);
+                cpu.pc = Address(0xC100);
+                for (offset, v) in bytes.iter().enumerate() {
+                    cpu.mmu.write(Address(0xC100 + offset as u16), *v).unwrap();
+                }
+
+                cpu.run_cycle().unwrap();
+                assert_eq!(
+                    cpu.cycle(),
+                    u64::from(i.cycles(taken)),
+                    "{} with flags {:#04x}",
+                    i,
+                    flags
+                );
+            }
+        }
+    }
+}
+
 // --------------- Test helpers ------------------
 
 fn make_test_cpu() -> Cpu {
+    make_test_cpu_in_mode(false)
+}
+
+fn make_test_cpu_in_mode(cgb: bool) -> Cpu {
     let mut v = Vec::new();
     v.resize(1024, 0);
+    if cgb {
+        v[0x143] = 0x80;
+    }
     let mock_cart = Cart::load(Cursor::new(v)).expect("Failed to create mock cart");
-    let mut cpu = Cpu::new(mock_cart, Box::new(NullSink), false);
+    let mut cpu = Cpu::new(mock_cart, Box::new(NullSink), cgb, None);
     cpu.pc = INTIAL_PC;
     for (r, v) in reg_defaults().iter() {
         cpu[*r] = *v;
@@ -231,6 +507,16 @@ fn make_test_cpu() -> Cpu {
     cpu
 }
 
+fn make_program_test_cpu(program: &[u8]) -> Cpu {
+    let mut cpu = make_test_cpu();
+    cpu.sp = Address(0xD000);
+    cpu.pc = Address(0xC100);
+    for (offset, v) in program.iter().enumerate() {
+        cpu.mmu.write(Address(0xC100 + offset as u16), *v).unwrap();
+    }
+    cpu
+}
+
 fn reg_set() -> HashSet<Register8> {
     let mut s = HashSet::new();
     s.insert(Register8::A);
@@ -25,6 +25,7 @@ const P15: u8 = 0b0010_0000;
 
 const INPUT_MASK: u8 = P10 | P11 | P12 | P13;
 const OUTPUT_MASK: u8 = P14 | P15;
+const P1_UNUSED_BITS: u8 = 0b1100_0000;
 
 impl Button {
     fn selected_by_output(self, output: u8) -> bool {
@@ -74,6 +75,11 @@ impl Input {
         self.p1 = output_bits | input_bits;
     }
 
+    // Whether any of the input lines P1 is looking at are held low
+    pub fn is_selected_button_pressed(&self) -> bool {
+        self.p1 & INPUT_MASK != INPUT_MASK
+    }
+
     pub fn activate_button(&mut self, button: Button) {
         self.active.insert(button);
         self.recalculate();
@@ -89,7 +95,7 @@ impl MemDevice for Input {
     fn read(&self, a: Address) -> Result<u8, ()> {
         assert_eq!(a, REG_P1);
 
-        Ok(self.p1)
+        Ok(self.p1 | P1_UNUSED_BITS)
     }
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
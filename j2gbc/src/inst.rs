@@ -1,8 +1,6 @@
 use std::fmt;
 use std::fmt::Display;
 
-use log::error;
-
 use super::alu::hi_lo;
 use super::cpu::{ConditionCode, Operand, Register16, Register8};
 use super::mem::Address;
@@ -34,17 +32,19 @@ pub enum Instruction {
     Control(Control),
     Load(Load),
     Logic(Logic),
+    // One of the unused opcodes, which hang the CPU
+    Illegal(u8),
 }
 
 impl Instruction {
     pub fn cycles(self, branch_taken: bool) -> u8 {
-        // TODO: Audit this list for accuracy
         match self {
             Instruction::Nop => 4,
             Instruction::EnableInterrupts => 4,
             Instruction::DisableInterrupts => 4,
             Instruction::Halt => 4,
             Instruction::Stop => 4,
+            Instruction::Illegal(_) => 4,
             Instruction::SetCarry | Instruction::ClearCarry => 4,
             Instruction::Compare(Operand::Immediate(_)) => 8,
             Instruction::Compare(Operand::IndirectRegister(_)) => 8,
@@ -67,16 +67,8 @@ impl Instruction {
             0xFB => Ok((Instruction::EnableInterrupts, 1)),
             0xF3 => Ok((Instruction::DisableInterrupts, 1)),
 
-            0x10 => match bytes[1] {
-                0x00 => Ok((Instruction::Stop, 2)),
-                _ => {
-                    error!(
-                        "Unknown instruction {:#X} {:#X} {:#X}",
-                        bytes[0], bytes[1], bytes[2]
-                    );
-                    Err(())
-                }
-            },
+            // The byte after STOP is skipped whatever it is
+            0x10 => Ok((Instruction::Stop, 2)),
             0x76 => Ok((Instruction::Halt, 1)),
 
             0x37 => Ok((Instruction::SetCarry, 1)),
@@ -453,11 +445,7 @@ impl Instruction {
                 )),
             },
             0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
-                error!(
-                    "Unknown instruction {:#X} {:#X} {:#X}",
-                    bytes[0], bytes[1], bytes[2]
-                );
-                Err(())
+                Ok((Instruction::Illegal(bytes[0]), 1))
             }
         }
     }
@@ -483,6 +471,7 @@ impl Display for Instruction {
             Instruction::Load(l) => l.fmt(f),
             Instruction::Control(c) => c.fmt(f),
             Instruction::Logic(l) => l.fmt(f),
+            Instruction::Illegal(op) => write!(f, "db {:#x}", op),
         }
     }
 }
@@ -48,7 +48,7 @@ impl Arith {
             | Arith::AddWithCarry(Operand::Immediate(_)) => 8,
 
             Arith::DecrementRegister16(_) | Arith::IncrementRegister16(_) => 8,
-            Arith::AddRegisterRegister16(_, _) => 16,
+            Arith::AddRegisterRegister16(_, _) => 8,
 
             Arith::AddSP(_) => 16,
 
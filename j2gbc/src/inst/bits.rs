@@ -25,7 +25,6 @@ pub enum Bits {
 
 impl Bits {
     pub fn cycles(self) -> u8 {
-        // TODO: Audit this list for accuracy
         match self {
             Bits::Complement => 4,
 
@@ -41,8 +40,9 @@ impl Bits {
             | Bits::ShiftRightArithmetic(Operand::Register(_))
             | Bits::ShiftRightLogical(Operand::Register(_)) => 8,
 
+            Bits::GetBit(_, Operand::IndirectRegister(_)) => 12,
+
             Bits::SetBit(_, Operand::IndirectRegister(_))
-            | Bits::GetBit(_, Operand::IndirectRegister(_))
             | Bits::ResetBit(_, Operand::IndirectRegister(_))
             | Bits::Swap(Operand::IndirectRegister(_))
             | Bits::RotateLeft(Operand::IndirectRegister(_))
@@ -51,7 +51,7 @@ impl Bits {
             | Bits::RotateRightCarry(Operand::IndirectRegister(_))
             | Bits::ShiftLeftArithmetic(Operand::IndirectRegister(_))
             | Bits::ShiftRightArithmetic(Operand::IndirectRegister(_))
-            | Bits::ShiftRightLogical(Operand::IndirectRegister(_)) => 8,
+            | Bits::ShiftRightLogical(Operand::IndirectRegister(_)) => 16,
 
             Bits::RotateRightAccumulator
             | Bits::RotateRightCarryAccumulator
@@ -1,11 +1,8 @@
-use std::cmp::max;
 use std::num::Wrapping;
 
-use j2ds::{next_timer_event, Timer, TimerEvent};
 use log::error;
 
 use crate::{
-    cpu::{Interrupt, InterruptSet, CLOCK_RATE},
     mem::{Address, MemDevice, Ram, RNG_CHAR_DAT, RNG_LCD_BGDD1, RNG_LCD_BGDD2, RNG_LCD_OAM},
     system::SystemMode,
 };
@@ -13,6 +10,7 @@ use crate::{
 mod bg;
 pub mod fb;
 mod obj;
+mod ppu;
 mod scanline;
 mod tile;
 
@@ -37,10 +35,7 @@ const BG_START_1: Address = Address(0x9800);
 const BG_START_2: Address = Address(0x9C00);
 
 const TOTAL_SCANLINES: u64 = 154;
-const LINE_CYCLE_TIME: u64 = CLOCK_RATE * 108_700 / 1_000_000_000; // Src: Official GB manual
-const HBLANK_DURATION: u64 = CLOCK_RATE * 48_600 / 1_000_000_000; // Src: GBCPUMan.pdf
-const MODE_10_DURATION: u64 = CLOCK_RATE * 19_000 / 1_000_000_000; // Src: GBCPUMan.pdf
-const VBLANK_DURATION: u64 = LINE_CYCLE_TIME * 10; // Src: Official GB manual
+const LINE_CYCLE_TIME: u64 = 456;
 const SCREEN_CYCLE_TIME: u64 = TOTAL_SCANLINES * LINE_CYCLE_TIME;
 const BYTES_PER_CHAR: u16 = 16;
 const BYTES_PER_ROW: u16 = 2;
@@ -49,15 +44,18 @@ const PIXEL_PER_CHAR: u8 = 8;
 
 const LYC_MATCH_INT_FLAG: u8 = 0b0100_0000;
 const MODE_10_INT_FLAG: u8 = 0b0010_0000;
-const _MODE_01_INT_FLAG: u8 = 0b0001_0000;
+const MODE_01_INT_FLAG: u8 = 0b0001_0000;
 const MODE_00_INT_FLAG: u8 = 0b0000_1000;
 
 const MODE_00_MASK: u8 = 0b00;
 const MODE_01_MASK: u8 = 0b01;
 const MODE_10_MASK: u8 = 0b10;
-const _MODE_11_MASK: u8 = 0b11;
+const MODE_11_MASK: u8 = 0b11;
 
 const LYC_MATCH_FLAG: u8 = 0b0000_0100;
+const STAT_UNUSED_BITS: u8 = 0b1000_0000;
+const VBK_UNUSED_BITS: u8 = 0b1111_1110;
+const CPS_UNUSED_BITS: u8 = 0b0100_0000;
 const BG_ENABLED_FLAG: u8 = 0b0000_0001;
 const WINDOW_ENABLED_FLAG: u8 = 0b0010_0000;
 const OAM_ENABLED_FLAG: u8 = 0b0000_0010;
@@ -103,9 +101,7 @@ pub struct Lcd {
     fbs: [fb::Framebuffer; 2],
     fbi: usize,
 
-    hblank_timer: Timer,
-    vblank_timer: Timer,
-    mode10_timer: Timer,
+    ppu: ppu::Ppu,
     scanline_sweeper: scanline::ScanlineSweeper,
 
     running_until_cycle: u64,
@@ -114,6 +110,15 @@ pub struct Lcd {
     objs: [obj::Obj; OBJ_COUNT],
 
     system_mode: SystemMode,
+    hblank_started: bool,
+    sprite_limit: bool,
+
+    stat_line: bool,
+    stat_irq: bool,
+
+    // A CGB running a DMG cart still colors it through the CGB palettes the
+    // boot ROM picked
+    dmg_compatibility: bool,
 }
 
 impl Lcd {
@@ -147,21 +152,7 @@ impl Lcd {
             obj_palettes: [[fb::DMG_COLOR_WHITE; 4]; 8],
             bg_palettes: [[fb::DMG_COLOR_WHITE; 4]; 8],
 
-            hblank_timer: Timer::new(
-                LINE_CYCLE_TIME,
-                LINE_CYCLE_TIME - HBLANK_DURATION - MODE_10_DURATION,
-                HBLANK_DURATION,
-            ),
-            vblank_timer: Timer::new(
-                SCREEN_CYCLE_TIME,
-                fb::SCREEN_SIZE.1 as u64 * LINE_CYCLE_TIME,
-                VBLANK_DURATION,
-            ),
-            mode10_timer: Timer::new(
-                LINE_CYCLE_TIME,
-                LINE_CYCLE_TIME - HBLANK_DURATION,
-                HBLANK_DURATION,
-            ),
+            ppu: ppu::Ppu::new(),
             running_until_cycle: 0,
 
             scanline_sweeper: scanline::ScanlineSweeper::new(),
@@ -174,6 +165,36 @@ impl Lcd {
             } else {
                 SystemMode::DMG
             },
+            hblank_started: false,
+            sprite_limit: true,
+            stat_line: false,
+            stat_irq: false,
+            dmg_compatibility: false,
+        }
+    }
+
+    // The state at power on, before a boot ROM has run
+    pub fn reset(&mut self) {
+        self.lcdc = 0;
+        self.turn_off();
+    }
+
+    pub fn set_dmg_compatibility(&mut self) {
+        self.system_mode = SystemMode::DMG;
+        self.dmg_compatibility = true;
+        self.bank_select = 0;
+    }
+
+    // Turning the limit off draws every sprite on a line, which stops the
+    // flicker games use to work around it
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.sprite_limit = limit;
+    }
+
+    // What's left on screen while the system is stopped
+    pub fn blank(&mut self) {
+        for fb in self.fbs.iter_mut() {
+            *fb = fb::Framebuffer::new(fb::SCREEN_SIZE);
         }
     }
 
@@ -197,151 +218,24 @@ impl Lcd {
         }
     }
 
-    pub fn get_next_event_cycle(&self) -> u64 {
-        next_timer_event(&[
-            self.hblank_timer,
-            self.vblank_timer,
-            self.mode10_timer,
-            self.scanline_sweeper.timer(),
-        ])
-    }
-
     pub fn set_running_until(&mut self, cycle: u64) {
         self.running_until_cycle = cycle;
     }
 
-    pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
-        let mut inters = InterruptSet::default();
-
-        let scanline_inter = self.scanline_sweeper.pump_cycle(cycle);
-        self.stat = (self.stat & !LYC_MATCH_FLAG) | self.scanline_sweeper.stat_flags();
-        if let Some(intr) = scanline_inter {
-            inters.add_interrupt(intr);
-        }
-
-        match self.hblank_timer.update(cycle) {
-            Some(TimerEvent::RisingEdge) => {
-                if self.scanline_sweeper.on_visible_scanline() {
-                    self.do_hblank_start(cycle);
-                    if self.is_hblank_int_enabled() {
-                        inters.add_interrupt(Interrupt::LCDC);
-                    }
-                }
-            }
-            Some(TimerEvent::FallingEdge) => {
-                self.do_hblank_end();
-            }
-            None => {}
-        }
-
-        match self.mode10_timer.update(cycle) {
-            Some(TimerEvent::RisingEdge) => {
-                self.stat = (self.stat & 0b1111_1100) | MODE_10_MASK;
-
-                if self.is_mode_10_int_enabled() {
-                    inters.add_interrupt(Interrupt::LCDC);
-                }
-            }
-            Some(TimerEvent::FallingEdge) => {
-                self.stat = (self.stat & 0b1111_1100) | MODE_00_MASK;
-            }
-            None => {}
-        }
-
-        match self.vblank_timer.update(cycle) {
-            Some(TimerEvent::RisingEdge) => {
-                self.do_vblank_start();
-                inters.add_interrupt(Interrupt::VBlank);
-            }
-            Some(TimerEvent::FallingEdge) => {
-                self.do_vblank_end();
-            }
-            None => {}
-        }
-
-        inters
-    }
-
-    fn do_hblank_start(&mut self, cycle: u64) {
-        if self.should_render_this_frame(cycle) {
-            self.render_screen_row();
-        }
-        self.stat = (self.stat & 0b1111_1100) | MODE_00_MASK;
-    }
-
     fn should_render_this_frame(&self, cycle: u64) -> bool {
         cycle >= self.running_until_cycle
             || self.running_until_cycle - cycle <= 2 * SCREEN_CYCLE_TIME
     }
 
-    fn do_hblank_end(&mut self) {}
-
-    pub fn do_vblank_start(&mut self) {
-        self.swap();
-        self.stat = (self.stat & 0b1111_1100) | MODE_01_MASK;
-    }
-
-    pub fn do_vblank_end(&mut self) {
-        self.stat = (self.stat & 0b1111_1100) | MODE_00_MASK;
-    }
-
-    fn render_screen_row(&mut self) {
-        let y = self.scanline_sweeper.ly() as usize;
-        if !self.is_lcd_enabled() {
-            for x in 0..(fb::SCREEN_SIZE.0 as usize) {
-                self.get_back_framebuffer().set(x, y, fb::DMG_COLOR_WHITE);
-            }
-            return;
-        }
-
-        let mut bg_screen_row =
-            [fb::TentativePixel::new(fb::DMG_COLOR_WHITE, false, true); fb::SCREEN_SIZE.0];
-        let mut oam_screen_row = [None; fb::SCREEN_SIZE.0];
-        self.render_background_row(&mut bg_screen_row);
-        self.render_window_row(&mut bg_screen_row);
-        self.render_oam_row(&mut oam_screen_row);
-
-        for x in 0..(fb::SCREEN_SIZE.0 as usize) {
-            let color = fb::resolve_pixel(self.system_mode, oam_screen_row[x], bg_screen_row[x]);
-
-            self.get_back_framebuffer().set(x, y, color);
-        }
+    // Whether an HBlank has begun since the last call, for HBlank HDMA
+    pub fn take_hblank_start(&mut self) -> bool {
+        let started = self.hblank_started;
+        self.hblank_started = false;
+        started
     }
 
-    fn render_background_row(&self, screen_row: &mut [fb::TentativePixel]) {
-        if !self.is_bg_enabled() {
-            return;
-        }
-        self.render_tile_row(
-            self.scanline_sweeper.ly(),
-            self.sx,
-            self.sy,
-            0,
-            self.get_bg_code_dat_start(),
-            screen_row,
-        );
-    }
-
-    fn render_window_row(&self, screen_row: &mut [fb::TentativePixel]) {
-        if !self.is_window_enabled() {
-            return;
-        }
-
-        let adjusted_wx = max(self.wx, 7) - 7;
-        let ly = self.scanline_sweeper.ly();
-        if self.wy > ly || adjusted_wx >= fb::SCREEN_SIZE.0 as u8 {
-            return;
-        }
-
-        let translated_y = ly - self.wy;
-        self.render_tile_row(
-            translated_y,
-            0,
-            0,
-            if self.wx > 7 { self.wx - 7 } else { 0 },
-            self.get_window_code_dat_start(),
-            screen_row,
-        );
+    pub fn is_in_hblank(&self) -> bool {
+        self.ppu.mode() == ppu::Mode::HBlank
     }
 
     fn render_tile_row(
@@ -354,7 +248,7 @@ impl Lcd {
         screen_row: &mut [fb::TentativePixel],
     ) {
         let translated_y = Wrapping(screen_y) + Wrapping(scy); // Implicit % 256
-        for screen_x in (start_x as usize)..screen_row.len() {
+        for (screen_x, pixel) in screen_row.iter_mut().enumerate().skip(usize::from(start_x)) {
             let translated_x = Wrapping(screen_x as u8) - Wrapping(start_x) + Wrapping(scx); // Implicit % 256
 
             let char_y_offset = Wrapping(u16::from(translated_y.0))
@@ -363,22 +257,7 @@ impl Lcd {
             let char_offset = Wrapping(u16::from(translated_x.0))
                 / Wrapping(u16::from(PIXEL_PER_CHAR))
                 + char_y_offset;
-            let (char_, flags) = if code_dat_start == RNG_LCD_BGDD1.0 {
-                (
-                    self.bgdd1.read(Address(char_offset.0)).unwrap(),
-                    self.bgdd1
-                        .read(Address(char_offset.0 + (RNG_LCD_BGDD1.len() as u16)))
-                        .unwrap(),
-                )
-            } else {
-                (
-                    self.bgdd2.read(Address(char_offset.0)).unwrap(),
-                    self.bgdd2
-                        .read(Address(char_offset.0 + (RNG_LCD_BGDD2.len() as u16)))
-                        .unwrap(),
-                )
-            };
-            let flags = bg::BgFlags::new(flags, self.system_mode);
+            let (char_, flags) = self.read_bg_map(code_dat_start, char_offset.0);
 
             let maybe_flipped_y = if flags.yflip() {
                 Wrapping(7) - (translated_y % Wrapping(8))
@@ -389,28 +268,70 @@ impl Lcd {
             let signed = self.get_bg_char_addr_start();
             let char_row = self.read_char_row_at(char_, maybe_flipped_y.0, signed, flags.bank());
 
-            let (color, data) = match self.system_mode {
-                SystemMode::CGB => {
-                    let maybe_flipped_x = if flags.xflip() {
-                        Wrapping(7) - (translated_x % Wrapping(8))
-                    } else {
-                        translated_x % Wrapping(8)
-                    };
-                    let color_index = char_row[maybe_flipped_x.0 as usize];
-                    (
-                        self.bg_palettes[flags.cgb_pallete() as usize][color_index as usize],
-                        color_index,
-                    )
-                }
-                SystemMode::DMG => {
-                    let color_index = char_row[(translated_x % Wrapping(8)).0 as usize];
-                    let corrected_index = palette_convert(color_index, self.bgp) as usize;
-                    (fb::DMG_COLORS[corrected_index], color_index)
-                }
+            let maybe_flipped_x = if flags.xflip() {
+                Wrapping(7) - (translated_x % Wrapping(8))
+            } else {
+                translated_x % Wrapping(8)
             };
+            let color_index = char_row[maybe_flipped_x.0 as usize];
+
+            *pixel = fb::TentativePixel::new(
+                self.bg_color(flags, color_index),
+                flags.priority(),
+                color_index == 0,
+            );
+        }
+    }
+
+    fn read_bg_map(&self, code_dat_start: Address, offset: u16) -> (u8, bg::BgFlags) {
+        let (char_, flags) = if code_dat_start == RNG_LCD_BGDD1.0 {
+            (
+                self.bgdd1.read(Address(offset)).unwrap(),
+                self.bgdd1
+                    .read(Address(offset + (RNG_LCD_BGDD1.len() as u16)))
+                    .unwrap(),
+            )
+        } else {
+            (
+                self.bgdd2.read(Address(offset)).unwrap(),
+                self.bgdd2
+                    .read(Address(offset + (RNG_LCD_BGDD2.len() as u16)))
+                    .unwrap(),
+            )
+        };
+        (char_, bg::BgFlags::new(flags, self.system_mode))
+    }
+
+    fn bg_color(&self, flags: bg::BgFlags, color_index: u8) -> fb::Pixel {
+        match self.system_mode {
+            SystemMode::CGB => self.bg_palettes[flags.cgb_pallete() as usize][color_index as usize],
+            SystemMode::DMG => {
+                let corrected_index = palette_convert(color_index, self.bgp) as usize;
+                if self.dmg_compatibility {
+                    self.bg_palettes[0][corrected_index]
+                } else {
+                    fb::DMG_COLORS[corrected_index]
+                }
+            }
+        }
+    }
 
-            screen_row[screen_x as usize] =
-                fb::TentativePixel::new(color, flags.priority(), data == 0);
+    fn obj_color(&self, obj: obj::Obj, color_index: u8) -> fb::Pixel {
+        match self.system_mode {
+            SystemMode::CGB => self.obj_palettes[obj.cgb_palette() as usize][color_index as usize],
+            SystemMode::DMG => {
+                let (pal, cgb_pal) = if obj.high_palette() {
+                    (self.obp1, 1)
+                } else {
+                    (self.obp0, 0)
+                };
+                let corrected_index = palette_convert(color_index, pal) as usize;
+                if self.dmg_compatibility {
+                    self.obj_palettes[cgb_pal][corrected_index]
+                } else {
+                    fb::DMG_COLORS[corrected_index]
+                }
+            }
         }
     }
 
@@ -476,16 +397,51 @@ impl Lcd {
         self.lcdc & OAM_ENABLED_FLAG != 0
     }
 
+    fn is_dmg_hardware(&self) -> bool {
+        match self.system_mode {
+            SystemMode::DMG => !self.dmg_compatibility,
+            SystemMode::CGB => false,
+        }
+    }
+
+    // The PPU keeps OAM to itself while scanning and drawing
+    pub fn is_oam_blocked(&self) -> bool {
+        self.is_lcd_enabled()
+            && match self.ppu.mode() {
+                ppu::Mode::OamScan | ppu::Mode::Drawing => true,
+                ppu::Mode::HBlank | ppu::Mode::VBlank => false,
+            }
+    }
+
     fn is_lcd_enabled(&self) -> bool {
         self.lcdc & LCD_ENABLED_FLAG != 0
     }
 
-    fn is_hblank_int_enabled(&self) -> bool {
-        self.stat & MODE_00_INT_FLAG != 0
+    // Every enabled STAT source is OR'ed onto one line, and only a rising
+    // edge on it requests an interrupt
+    fn stat_sources(&self, enabled: u8) -> bool {
+        let mode = match self.ppu.mode() {
+            ppu::Mode::HBlank => MODE_00_INT_FLAG,
+            ppu::Mode::VBlank => MODE_01_INT_FLAG,
+            ppu::Mode::OamScan => MODE_10_INT_FLAG,
+            ppu::Mode::Drawing => 0,
+        };
+        let lyc = if self.scanline_sweeper.stat_flags() & LYC_MATCH_FLAG != 0 {
+            LYC_MATCH_INT_FLAG
+        } else {
+            0
+        };
+        enabled & (mode | lyc) != 0
     }
 
-    fn is_mode_10_int_enabled(&self) -> bool {
-        self.stat & MODE_10_INT_FLAG != 0
+    fn update_stat_line(&mut self) {
+        self.stat = (self.stat & !LYC_MATCH_FLAG) | self.scanline_sweeper.stat_flags();
+
+        let line = self.stat_sources(self.stat);
+        if line && !self.stat_line {
+            self.stat_irq = true;
+        }
+        self.stat_line = line;
     }
 
     fn get_bg_char_addr_start(&self) -> bool {
@@ -520,69 +476,6 @@ impl Lcd {
         )
     }
 
-    fn render_oam_row(&self, screen_row: &mut [Option<fb::TentativePixel>]) {
-        if !self.is_oam_enabled() {
-            return;
-        }
-
-        for i in 0..OBJ_COUNT {
-            let obj = self.objs[OBJ_COUNT - i - 1];
-
-            let (char_, hi_y) = if self.lcdc & OAM_TALL_FLAG != 0 {
-                (obj.char_ & 0b1111_1110, 16)
-            } else {
-                (obj.char_, 8)
-            };
-
-            for y in 0..hi_y {
-                let full_y = y as isize + obj.y as isize - 16;
-                if full_y > fb::SCREEN_SIZE.1 as isize
-                    || full_y < 0
-                    || full_y != self.scanline_sweeper.ly() as isize
-                {
-                    continue;
-                }
-
-                let index_y = if obj.yflip() { hi_y - 1 - y } else { y };
-                let row = self.read_char_row_at(char_, index_y, false, obj.bank());
-                for x in 0..8 {
-                    let full_x = x as isize + obj.x as isize - 8;
-
-                    if full_x >= fb::SCREEN_SIZE.0 as isize || full_x < 0 {
-                        continue;
-                    }
-
-                    let index_x = if obj.xflip() { 7 - x } else { x };
-                    let color_index = row[index_x as usize];
-                    if color_index == 0 {
-                        // 0 is always transparent
-                        continue;
-                    }
-                    let color = match self.system_mode {
-                        SystemMode::CGB => {
-                            self.obj_palettes[obj.cgb_palette() as usize][color_index as usize]
-                        }
-                        SystemMode::DMG => {
-                            let pal = if obj.high_palette() {
-                                self.obp1
-                            } else {
-                                self.obp0
-                            };
-                            let corrected_index = palette_convert(color_index, pal) as usize;
-                            fb::DMG_COLORS[corrected_index]
-                        }
-                    };
-
-                    screen_row[full_x as usize] = Some(fb::TentativePixel::new(
-                        color,
-                        !obj.priority(),
-                        color_index == 0,
-                    ));
-                }
-            }
-        }
-    }
-
     fn update_tile_at(&mut self, a: Address) {
         let byte_offset = a - RNG_CHAR_DAT.0;
         let char_offset = byte_offset.0 / BYTES_PER_CHAR;
@@ -652,7 +545,7 @@ impl MemDevice for Lcd {
             match a {
                 REG_LY => Ok(self.scanline_sweeper.ly()),
                 REG_LYC => Ok(self.scanline_sweeper.lyc()),
-                REG_STAT => Ok(self.stat),
+                REG_STAT => Ok(self.stat | STAT_UNUSED_BITS),
                 REG_LCDC => Ok(self.lcdc),
                 REG_OBP0 => Ok(self.obp0),
                 REG_OBP1 => Ok(self.obp1),
@@ -660,19 +553,14 @@ impl MemDevice for Lcd {
                 REG_WY => Ok(self.wy),
                 REG_SCX => Ok(self.sx),
                 REG_SCY => Ok(self.sy),
-                REG_VBK => Ok(self.bank_select as u8),
-                REG_BCPS => Ok(self.bcps),
+                REG_VBK => Ok(self.bank_select as u8 | VBK_UNUSED_BITS),
+                REG_BCPS => Ok(self.bcps | CPS_UNUSED_BITS),
                 REG_BCPD => Ok(self.bcp[(self.bcps & PAL_DATA_IDX) as usize]),
-                REG_OCPS => Ok(self.ocps),
+                REG_OCPS => Ok(self.ocps | CPS_UNUSED_BITS),
                 REG_OCPD => Ok(self.ocp[(self.ocps & PAL_DATA_IDX) as usize]),
-                REG_BGP => {
-                    error!("Error: BGP is a write-only register");
-                    Err(())
-                }
-                _ => {
-                    error!("Unimplemented LCD register {:?}", a);
-                    Err(())
-                }
+                REG_BGP => Ok(self.bgp),
+                // Open bus
+                _ => Ok(0xFF),
             }
         }
     }
@@ -701,15 +589,28 @@ impl MemDevice for Lcd {
                 }
                 REG_LYC => {
                     self.scanline_sweeper.set_lyc(v);
+                    self.update_stat_line();
                     Ok(())
                 }
                 REG_LCDC => {
+                    let was_enabled = self.is_lcd_enabled();
                     self.lcdc = v;
+                    match (was_enabled, self.is_lcd_enabled()) {
+                        (true, false) => self.turn_off(),
+                        (false, true) => self.turn_on(),
+                        _ => {}
+                    }
                     Ok(())
                 }
                 REG_STAT => {
-                    self.stat = v & 0b1111_1100;
-                    self.scanline_sweeper.update_stat(v);
+                    // A DMG briefly acts as if the mode 0, mode 1 and LYC
+                    // sources were all enabled
+                    let glitch = MODE_00_INT_FLAG | MODE_01_INT_FLAG | LYC_MATCH_INT_FLAG;
+                    if !self.stat_line && self.is_dmg_hardware() && self.stat_sources(glitch) {
+                        self.stat_irq = true;
+                    }
+                    self.stat = (v & 0b1111_1100) | (self.stat & 0b11);
+                    self.update_stat_line();
                     Ok(())
                 }
                 REG_BGP => {
@@ -778,10 +679,7 @@ impl MemDevice for Lcd {
                     load_color_from_data(&self.ocp, &mut self.obj_palettes);
                     Ok(())
                 }
-                _ => {
-                    error!("Unimplemented LCD register {:?}", a);
-                    Err(())
-                }
+                _ => Ok(()),
             }
         }
     }
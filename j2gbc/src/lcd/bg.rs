@@ -6,7 +6,7 @@ const BG_PRI_FLAG: u8 = 0b1000_0000;
 const BG_BANK_SEL_MASK: u8 = 0b0000_1000;
 const BG_PAL_SEL_MASK: u8 = 0b0000_0111;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct BgFlags {
     flags: u8,
 }
@@ -0,0 +1,706 @@
+use super::{
+    bg::BgFlags, fb, obj::Obj, tile::MonoTileRow, Lcd, BG_CHARS_PER_ROW, LINE_CYCLE_TIME,
+    MODE_00_MASK, MODE_01_MASK, MODE_10_MASK, MODE_11_MASK, PIXEL_PER_CHAR,
+};
+use crate::{
+    cpu::{Interrupt, InterruptSet},
+    system::SystemMode,
+};
+
+const OAM_SCAN_DURATION: u64 = 80;
+const LY_WRAP_DOTS: u64 = 4;
+// The first fetch of a line is thrown away
+const FIRST_FETCH_DOTS: u8 = 6;
+const FETCH_STEP_DOTS: u8 = 2;
+const SPRITE_FETCH_DOTS: u8 = 6;
+const FIFO_LEN: usize = 8;
+const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Mode {
+    OamScan,
+    Drawing,
+    HBlank,
+    VBlank,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color_index: u8,
+    flags: BgFlags,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color_index: u8,
+    obj: Obj,
+    index: u8,
+}
+
+// Where the pixel pipeline is within the frame, and everything it is holding
+// on to while drawing a line.
+pub struct Ppu {
+    mode: Mode,
+    line_start: u64,
+    ly_wrap_pending: bool,
+    // The next dot to run
+    cycle: u64,
+
+    x: u8,
+    discard: u8,
+    render: bool,
+
+    fetch_stall: u8,
+    fetch_step: FetchStep,
+    fetch_dots: u8,
+    fetch_x: u8,
+    fetch_flags: BgFlags,
+    fetch_char: u8,
+    fetch_row: MonoTileRow,
+
+    bg_fifo: [BgPixel; FIFO_LEN],
+    bg_fifo_pos: usize,
+    bg_fifo_len: usize,
+    obj_fifo: [ObjPixel; FIFO_LEN],
+
+    // Sprites on this line that haven't been fetched yet, in OAM order
+    sprites: Vec<(u8, Obj)>,
+    sprite_fetch: Option<(u8, Obj)>,
+    sprite_fetch_dots: u8,
+
+    window_active: bool,
+    window_y_triggered: bool,
+    window_line: u8,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            mode: Mode::OamScan,
+            line_start: 0,
+            ly_wrap_pending: false,
+            cycle: 0,
+
+            x: 0,
+            discard: 0,
+            render: false,
+
+            fetch_stall: 0,
+            fetch_step: FetchStep::Tile,
+            fetch_dots: 0,
+            fetch_x: 0,
+            fetch_flags: BgFlags::default(),
+            fetch_char: 0,
+            fetch_row: MonoTileRow::default(),
+
+            bg_fifo: [BgPixel::default(); FIFO_LEN],
+            bg_fifo_pos: 0,
+            bg_fifo_len: 0,
+            obj_fifo: [ObjPixel::default(); FIFO_LEN],
+
+            sprites: Vec::new(),
+            sprite_fetch: None,
+            sprite_fetch_dots: 0,
+
+            window_active: false,
+            window_y_triggered: false,
+            window_line: 0,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn next_mode_change(&self) -> u64 {
+        match self.mode {
+            Mode::OamScan => self.line_start + OAM_SCAN_DURATION,
+            // Can't finish any sooner than a pixel a dot
+            Mode::Drawing => self.cycle + u64::from(fb::SCREEN_SIZE.0 as u8 - 1 - self.x),
+            Mode::VBlank if self.ly_wrap_pending => self.line_start + LY_WRAP_DOTS,
+            Mode::HBlank | Mode::VBlank => self.line_start + LINE_CYCLE_TIME,
+        }
+    }
+}
+
+impl Lcd {
+    pub fn get_next_event_cycle(&self) -> u64 {
+        if self.is_lcd_enabled() {
+            self.ppu.next_mode_change()
+        } else {
+            u64::MAX
+        }
+    }
+
+    pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
+        let mut inters = InterruptSet::default();
+
+        // The PPU is held at the start of line 0 while the LCD is off
+        if !self.is_lcd_enabled() {
+            self.ppu.cycle = cycle + 1;
+            self.stat_irq = false;
+            return inters;
+        }
+
+        while self.ppu.cycle <= cycle {
+            if self.ppu.mode == Mode::Drawing {
+                self.draw_dot();
+                self.update_stat_line();
+                self.ppu.cycle += 1;
+                continue;
+            }
+
+            // Nothing happens between mode changes
+            let next = self.ppu.next_mode_change();
+            if next > cycle {
+                self.ppu.cycle = cycle + 1;
+                break;
+            }
+            self.ppu.cycle = next;
+            match self.ppu.mode {
+                Mode::OamScan => self.start_drawing(),
+                Mode::VBlank if self.ppu.ly_wrap_pending => {
+                    self.ppu.ly_wrap_pending = false;
+                    self.scanline_sweeper.wrap_ly();
+                }
+                _ => self.start_line(&mut inters),
+            }
+            self.update_stat_line();
+        }
+
+        if self.stat_irq {
+            self.stat_irq = false;
+            inters.add_interrupt(Interrupt::LCDC);
+        }
+        inters
+    }
+
+    pub fn turn_off(&mut self) {
+        self.scanline_sweeper.restart(self.ppu.cycle);
+        self.ppu.ly_wrap_pending = false;
+        self.set_mode(Mode::HBlank);
+        self.stat_line = false;
+        self.blank();
+    }
+
+    // Drawing starts over from the top of the screen
+    pub fn turn_on(&mut self) {
+        self.scanline_sweeper.restart(self.ppu.cycle);
+        self.ppu.line_start = self.ppu.cycle;
+        self.ppu.window_line = 0;
+        self.ppu.window_y_triggered = false;
+        self.set_mode(Mode::OamScan);
+        self.update_stat_line();
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.ppu.mode = mode;
+        let mask = match mode {
+            Mode::HBlank => MODE_00_MASK,
+            Mode::VBlank => MODE_01_MASK,
+            Mode::OamScan => MODE_10_MASK,
+            Mode::Drawing => MODE_11_MASK,
+        };
+        self.stat = (self.stat & 0b1111_1100) | mask;
+    }
+
+    fn start_line(&mut self, inters: &mut InterruptSet) {
+        self.ppu.line_start = self.ppu.cycle;
+        self.scanline_sweeper.pump_cycle(self.ppu.cycle);
+        self.ppu.ly_wrap_pending = self.scanline_sweeper.is_last_line();
+
+        if self.scanline_sweeper.line() == 0 {
+            self.ppu.window_line = 0;
+            self.ppu.window_y_triggered = false;
+        }
+
+        if self.scanline_sweeper.on_visible_scanline() {
+            self.set_mode(Mode::OamScan);
+        } else if self.ppu.mode != Mode::VBlank {
+            self.swap();
+            self.set_mode(Mode::VBlank);
+            inters.add_interrupt(Interrupt::VBlank);
+        }
+    }
+
+    fn start_drawing(&mut self) {
+        let ly = i16::from(self.scanline_sweeper.ly());
+        let height = if self.lcdc & super::OAM_TALL_FLAG != 0 {
+            16
+        } else {
+            8
+        };
+
+        // WY is compared against LY during OAM scan of every line
+        if ly == i16::from(self.wy) {
+            self.ppu.window_y_triggered = true;
+        }
+
+        // Sprites count toward the limit even when their X is off screen
+        self.ppu.sprites.clear();
+        for (i, obj) in self.objs.iter().enumerate() {
+            if self.sprite_limit && self.ppu.sprites.len() == SPRITES_PER_LINE {
+                break;
+            }
+            let top = i16::from(obj.y) - 16;
+            if ly >= top && ly < top + height {
+                self.ppu.sprites.push((i as u8, *obj));
+            }
+        }
+        // A DMG fetches sprites left to right, and the first one fetched wins
+        if let SystemMode::DMG = self.system_mode {
+            self.ppu.sprites.sort_by_key(|(_, obj)| obj.x);
+        }
+        self.ppu.sprite_fetch = None;
+
+        self.ppu.x = 0;
+        self.ppu.discard = self.sx % PIXEL_PER_CHAR;
+        self.ppu.render = self.should_render_this_frame(self.ppu.cycle);
+        self.ppu.fetch_stall = FIRST_FETCH_DOTS;
+        self.ppu.fetch_step = FetchStep::Tile;
+        self.ppu.fetch_dots = 0;
+        self.ppu.fetch_x = 0;
+        self.ppu.bg_fifo_len = 0;
+        self.ppu.obj_fifo = [ObjPixel::default(); FIFO_LEN];
+        self.ppu.window_active = false;
+
+        self.set_mode(Mode::Drawing);
+    }
+
+    fn start_hblank(&mut self) {
+        if self.ppu.window_active {
+            self.ppu.window_line = self.ppu.window_line.wrapping_add(1);
+        }
+
+        self.set_mode(Mode::HBlank);
+        self.hblank_started = self.is_lcd_enabled();
+    }
+
+    fn draw_dot(&mut self) {
+        let x = self.ppu.x;
+
+        if !self.ppu.window_active
+            && self.is_window_enabled()
+            && self.ppu.window_y_triggered
+            && u16::from(x) + 7 >= u16::from(self.wx)
+        {
+            // The window throws away what was fetched and starts over
+            self.ppu.window_active = true;
+            self.ppu.bg_fifo_len = 0;
+            self.ppu.fetch_step = FetchStep::Tile;
+            self.ppu.fetch_dots = 0;
+            self.ppu.fetch_x = 0;
+        }
+
+        if self.ppu.sprite_fetch.is_none() && self.ppu.discard == 0 && self.is_oam_enabled() {
+            let pos = self
+                .ppu
+                .sprites
+                .iter()
+                .position(|(_, obj)| i16::from(obj.x) - 8 <= i16::from(x));
+            if let Some(pos) = pos {
+                self.ppu.sprite_fetch = Some(self.ppu.sprites.remove(pos));
+                self.ppu.sprite_fetch_dots = 0;
+            }
+        }
+
+        if let Some((index, obj)) = self.ppu.sprite_fetch {
+            // Sprites wait for the background fetch in progress to finish
+            if self.ppu.fetch_stall > 0 || self.ppu.fetch_step != FetchStep::Push {
+                self.tick_fetcher();
+            } else {
+                self.ppu.sprite_fetch_dots += 1;
+                if self.ppu.sprite_fetch_dots == SPRITE_FETCH_DOTS {
+                    self.merge_sprite(index, obj);
+                    self.ppu.sprite_fetch = None;
+                }
+            }
+            return;
+        }
+
+        self.tick_fetcher();
+
+        if self.ppu.bg_fifo_len == 0 {
+            return;
+        }
+        let bg = self.ppu.bg_fifo[self.ppu.bg_fifo_pos];
+        self.ppu.bg_fifo_pos += 1;
+        self.ppu.bg_fifo_len -= 1;
+
+        if self.ppu.discard > 0 {
+            self.ppu.discard -= 1;
+            return;
+        }
+
+        let obj = self.ppu.obj_fifo[0];
+        self.ppu.obj_fifo.copy_within(1.., 0);
+        self.ppu.obj_fifo[FIFO_LEN - 1] = ObjPixel::default();
+
+        if self.ppu.render {
+            self.output_pixel(bg, obj);
+        }
+
+        self.ppu.x += 1;
+        if usize::from(self.ppu.x) == fb::SCREEN_SIZE.0 {
+            self.start_hblank();
+        }
+    }
+
+    fn tick_fetcher(&mut self) {
+        if self.ppu.fetch_stall > 0 {
+            self.ppu.fetch_stall -= 1;
+            return;
+        }
+
+        if self.ppu.fetch_step == FetchStep::Push {
+            if self.ppu.bg_fifo_len == 0 {
+                self.push_bg_pixels();
+                self.ppu.fetch_x = self.ppu.fetch_x.wrapping_add(1);
+                self.ppu.fetch_step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        self.ppu.fetch_dots += 1;
+        if self.ppu.fetch_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        self.ppu.fetch_dots = 0;
+
+        self.ppu.fetch_step = match self.ppu.fetch_step {
+            FetchStep::Tile => {
+                self.fetch_tile();
+                FetchStep::DataLow
+            }
+            FetchStep::DataLow => FetchStep::DataHigh,
+            _ => {
+                self.fetch_tile_row();
+                FetchStep::Push
+            }
+        };
+    }
+
+    fn fetch_tile(&mut self) {
+        let (map, map_x, map_y) = if self.ppu.window_active {
+            (
+                self.get_window_code_dat_start(),
+                self.ppu.fetch_x,
+                self.ppu.window_line,
+            )
+        } else {
+            (
+                self.get_bg_code_dat_start(),
+                (self.sx / PIXEL_PER_CHAR).wrapping_add(self.ppu.fetch_x),
+                self.scanline_sweeper.ly().wrapping_add(self.sy),
+            )
+        };
+
+        let offset = u16::from(map_y / PIXEL_PER_CHAR) * u16::from(BG_CHARS_PER_ROW)
+            + u16::from(map_x % BG_CHARS_PER_ROW);
+        let (char_, flags) = self.read_bg_map(map, offset);
+        self.ppu.fetch_char = char_;
+        self.ppu.fetch_flags = flags;
+    }
+
+    fn fetch_tile_row(&mut self) {
+        let y = if self.ppu.window_active {
+            self.ppu.window_line
+        } else {
+            self.scanline_sweeper.ly().wrapping_add(self.sy)
+        } % PIXEL_PER_CHAR;
+
+        let flags = self.ppu.fetch_flags;
+        let row = if flags.yflip() { 7 - y } else { y };
+        self.ppu.fetch_row = self.read_char_row_at(
+            self.ppu.fetch_char,
+            row,
+            self.get_bg_char_addr_start(),
+            flags.bank(),
+        );
+    }
+
+    fn push_bg_pixels(&mut self) {
+        let flags = self.ppu.fetch_flags;
+        for i in 0..FIFO_LEN {
+            let col = if flags.xflip() { 7 - i } else { i };
+            self.ppu.bg_fifo[i] = BgPixel {
+                color_index: self.ppu.fetch_row[col],
+                flags,
+            };
+        }
+        self.ppu.bg_fifo_pos = 0;
+        self.ppu.bg_fifo_len = FIFO_LEN;
+    }
+
+    fn merge_sprite(&mut self, index: u8, obj: Obj) {
+        let (char_, height) = if self.lcdc & super::OAM_TALL_FLAG != 0 {
+            (obj.char_ & 0b1111_1110, 16)
+        } else {
+            (obj.char_, 8)
+        };
+        let y = self
+            .scanline_sweeper
+            .ly()
+            .wrapping_add(16)
+            .wrapping_sub(obj.y);
+        let row = if obj.yflip() { height - 1 - y } else { y };
+        let pixels = self.read_char_row_at(char_, row, false, obj.bank());
+
+        let left = i16::from(obj.x) - 8;
+        for i in 0..FIFO_LEN {
+            let slot = left + i as i16 - i16::from(self.ppu.x);
+            if slot < 0 || slot >= FIFO_LEN as i16 {
+                continue;
+            }
+
+            let col = if obj.xflip() { 7 - i } else { i };
+            let color_index = pixels[col];
+            let existing = &mut self.ppu.obj_fifo[slot as usize];
+            let wins = match self.system_mode {
+                SystemMode::DMG => existing.color_index == 0,
+                SystemMode::CGB => existing.color_index == 0 || index < existing.index,
+            };
+            if color_index != 0 && wins {
+                *existing = ObjPixel {
+                    color_index,
+                    obj,
+                    index,
+                };
+            }
+        }
+    }
+
+    fn output_pixel(&mut self, bg: BgPixel, obj: ObjPixel) {
+        let y = usize::from(self.scanline_sweeper.ly());
+        let x = usize::from(self.ppu.x);
+
+        let bg = if self.is_bg_enabled() {
+            fb::TentativePixel::new(
+                self.bg_color(bg.flags, bg.color_index),
+                bg.flags.priority(),
+                bg.color_index == 0,
+            )
+        } else {
+            fb::TentativePixel::new(fb::DMG_COLOR_WHITE, false, true)
+        };
+        let obj = if obj.color_index != 0 && self.is_oam_enabled() {
+            Some(fb::TentativePixel::new(
+                self.obj_color(obj.obj, obj.color_index),
+                !obj.obj.priority(),
+                false,
+            ))
+        } else {
+            None
+        };
+        let color = fb::resolve_pixel(self.system_mode, obj, bg);
+
+        self.get_back_framebuffer().set(x, y, color);
+    }
+}
+
+#[cfg(test)]
+fn mode3_length(lcd: &mut Lcd) -> u64 {
+    lcd.pump_cycle(OAM_SCAN_DURATION - 1);
+    let mut cycle = OAM_SCAN_DURATION;
+    loop {
+        lcd.pump_cycle(cycle);
+        if lcd.ppu.mode() != Mode::Drawing {
+            return cycle - OAM_SCAN_DURATION + 1;
+        }
+        cycle += 1;
+    }
+}
+
+#[test]
+fn test_mode3_length() {
+    use crate::mem::{Address, MemDevice};
+
+    assert_eq!(mode3_length(&mut Lcd::new(false)), 172);
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0xFF43), 3).unwrap();
+    assert_eq!(mode3_length(&mut lcd), 175);
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0xFF40), 0x83 | super::WINDOW_ENABLED_FLAG)
+        .unwrap();
+    lcd.write(Address(0xFF4B), 7 + 80).unwrap();
+    assert_eq!(mode3_length(&mut lcd), 178);
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0xFE00), 16).unwrap();
+    lcd.write(Address(0xFE01), 8 + 40).unwrap();
+    let length = mode3_length(&mut lcd);
+    assert!((172 + 6..=172 + 11).contains(&length));
+}
+
+#[test]
+fn test_mid_scanline_palette_change() {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    // Pixels go out a dot at a time after the first two fetches
+    lcd.pump_cycle(OAM_SCAN_DURATION + 12 + 79);
+    lcd.write(Address(0xFF47), 0b11).unwrap();
+    lcd.pump_cycle(LINE_CYCLE_TIME * fb::SCREEN_SIZE.1 as u64);
+
+    let fb = lcd.get_framebuffer();
+    assert_eq!(fb.get(79, 0), fb::DMG_COLOR_WHITE);
+    assert_eq!(fb.get(80, 0), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(0, 1), fb::DMG_COLOR_BLACK);
+}
+
+#[cfg(test)]
+fn draw_sprites(limit: bool, sprites: &[(u8, u8)]) -> fb::Framebuffer {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    lcd.set_sprite_limit(limit);
+    lcd.write(Address(0xFF48), 0b1100).unwrap();
+    lcd.write(Address(0xFF49), 0b0100).unwrap();
+    lcd.write(Address(0x8000), 0xFF).unwrap();
+    for (i, &(x, flags)) in sprites.iter().enumerate() {
+        let a = 0xFE00 + 4 * i as u16;
+        lcd.write(Address(a), 16).unwrap();
+        lcd.write(Address(a + 1), x).unwrap();
+        lcd.write(Address(a + 3), flags).unwrap();
+    }
+    lcd.pump_cycle(LINE_CYCLE_TIME * fb::SCREEN_SIZE.1 as u64);
+    lcd.get_framebuffer().clone()
+}
+
+#[test]
+fn test_sprite_limit() {
+    // Ten sprites off the left edge use up the line before the eleventh
+    let mut sprites = vec![(0, 0); 10];
+    sprites.push((8, 0));
+
+    let fb = draw_sprites(true, &sprites);
+    assert_eq!(fb.get(0, 0), fb::DMG_COLOR_WHITE);
+
+    let fb = draw_sprites(false, &sprites);
+    assert_eq!(fb.get(0, 0), fb::DMG_COLOR_BLACK);
+}
+
+#[test]
+fn test_dmg_sprite_x_priority() {
+    // The later sprite in OAM is further left, so it wins where they overlap
+    let fb = draw_sprites(true, &[(12, 0b0001_0000), (8, 0)]);
+    assert_eq!(fb.get(3, 0), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(4, 0), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(8, 0), fb::DMG_COLOR_LIGHT_GRAY);
+}
+
+#[cfg(test)]
+fn stat_interrupts(lcd: &mut Lcd, start: u64, end: u64) -> Vec<u64> {
+    (start..end)
+        .filter(|&cycle| lcd.pump_cycle(cycle).if_() & Interrupt::LCDC.bits() != 0)
+        .collect()
+}
+
+#[test]
+fn test_stat_line_blocking() {
+    use crate::mem::{Address, MemDevice};
+
+    // Mode 0 runs straight into mode 2, so the line never drops between them
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        Address(0xFF41),
+        super::MODE_00_INT_FLAG | super::MODE_10_INT_FLAG,
+    )
+    .unwrap();
+    let start = LINE_CYCLE_TIME * 10;
+    lcd.pump_cycle(start - 1);
+    let fired = stat_interrupts(&mut lcd, start, start + LINE_CYCLE_TIME * 10);
+    assert_eq!(fired.len(), 10);
+    assert!(fired
+        .iter()
+        .all(|cycle| cycle % LINE_CYCLE_TIME > OAM_SCAN_DURATION));
+}
+
+#[test]
+fn test_stat_mode_1() {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0xFF41), super::MODE_01_INT_FLAG).unwrap();
+    // LY matches LYC at power on, so the write itself trips the DMG glitch
+    lcd.pump_cycle(0);
+    let frame = LINE_CYCLE_TIME * super::TOTAL_SCANLINES;
+    let fired = stat_interrupts(&mut lcd, 1, frame);
+    assert_eq!(fired, vec![LINE_CYCLE_TIME * fb::SCREEN_SIZE.1 as u64]);
+}
+
+#[test]
+fn test_stat_lyc_on_last_line() {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0xFF41), super::LYC_MATCH_INT_FLAG)
+        .unwrap();
+    lcd.pump_cycle(0);
+    // LY reads 0 just after line 153 starts, and stays matched into line 0
+    let frame = LINE_CYCLE_TIME * super::TOTAL_SCANLINES;
+    let fired = stat_interrupts(&mut lcd, 1, frame + LINE_CYCLE_TIME);
+    assert_eq!(fired, vec![frame - LINE_CYCLE_TIME + LY_WRAP_DOTS]);
+}
+
+#[test]
+fn test_dmg_stat_write_glitch() {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    lcd.pump_cycle(LINE_CYCLE_TIME - 1);
+    assert_eq!(lcd.ppu.mode(), Mode::HBlank);
+    lcd.write(Address(0xFF41), 0).unwrap();
+    assert_ne!(lcd.pump_cycle(LINE_CYCLE_TIME - 1).if_(), 0);
+
+    let mut lcd = Lcd::new(true);
+    lcd.pump_cycle(LINE_CYCLE_TIME - 1);
+    lcd.write(Address(0xFF41), 0).unwrap();
+    assert_eq!(lcd.pump_cycle(LINE_CYCLE_TIME - 1).if_(), 0);
+}
+
+#[test]
+fn test_lcd_off() {
+    use crate::mem::{Address, MemDevice};
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        Address(0xFF41),
+        super::MODE_00_INT_FLAG | super::MODE_01_INT_FLAG | super::MODE_10_INT_FLAG,
+    )
+    .unwrap();
+    let off = LINE_CYCLE_TIME * 10 + 100;
+    lcd.pump_cycle(off - 1);
+    lcd.write(Address(0xFF40), 0x03).unwrap();
+    assert_eq!(lcd.read(Address(0xFF44)), Ok(0));
+    assert_eq!(lcd.read(Address(0xFF41)).unwrap() & 0b11, 0);
+
+    let frame = LINE_CYCLE_TIME * super::TOTAL_SCANLINES;
+    for cycle in off..off + frame {
+        assert_eq!(lcd.pump_cycle(cycle).if_(), 0);
+    }
+    assert_eq!(lcd.read(Address(0xFF44)), Ok(0));
+    assert_eq!(lcd.ppu.mode(), Mode::HBlank);
+
+    let on = off + frame;
+    lcd.write(Address(0xFF40), 0x83).unwrap();
+    assert_eq!(lcd.ppu.mode(), Mode::OamScan);
+    lcd.pump_cycle(on + OAM_SCAN_DURATION - 1);
+    assert_eq!(lcd.ppu.mode(), Mode::OamScan);
+    lcd.pump_cycle(on + OAM_SCAN_DURATION);
+    assert_eq!(lcd.ppu.mode(), Mode::Drawing);
+    lcd.pump_cycle(on + LINE_CYCLE_TIME - 1);
+    assert_eq!(lcd.read(Address(0xFF44)), Ok(0));
+    lcd.pump_cycle(on + LINE_CYCLE_TIME);
+    assert_eq!(lcd.read(Address(0xFF44)), Ok(1));
+}
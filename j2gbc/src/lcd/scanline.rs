@@ -1,46 +1,68 @@
 use j2ds::{Timer, TimerEvent};
 
-use super::{LINE_CYCLE_TIME, LYC_MATCH_FLAG, LYC_MATCH_INT_FLAG, TOTAL_SCANLINES};
-use crate::cpu::Interrupt;
+use super::{LINE_CYCLE_TIME, LYC_MATCH_FLAG, TOTAL_SCANLINES};
 
 pub struct ScanlineSweeper {
+    line: u8,
+    // What LY reads as, which isn't always the line being drawn
     ly: u8,
     lyc: u8,
-    interrupt_enabled: bool,
+    // When line 0 started
+    start: u64,
     timer: Timer,
 }
 
+fn line_timer() -> Timer {
+    let mut timer = Timer::new(LINE_CYCLE_TIME, 0, 0);
+    timer.update(0);
+    timer
+}
+
 impl ScanlineSweeper {
     pub fn new() -> ScanlineSweeper {
-        let mut timer = Timer::new(LINE_CYCLE_TIME, 0, 0);
-        timer.update(0);
         ScanlineSweeper {
+            line: 0,
             ly: 0,
             lyc: 0,
-            interrupt_enabled: false,
-            timer,
+            start: 0,
+            timer: line_timer(),
         }
     }
 
-    pub fn pump_cycle(&mut self, cycle: u64) -> Option<Interrupt> {
+    pub fn pump_cycle(&mut self, cycle: u64) {
+        let cycle = cycle - self.start;
         if self.timer.update(cycle) == Some(TimerEvent::RisingEdge) {
             assert_eq!(self.timer.update(cycle), None); // We should never end up too far behind
-            self.ly = (self.ly + 1) % TOTAL_SCANLINES as u8;
-
-            if self.ly == self.lyc && self.interrupt_enabled {
-                Some(Interrupt::LCDC)
-            } else {
-                None
-            }
-        } else {
-            None
+            self.line = (self.line + 1) % TOTAL_SCANLINES as u8;
+            self.ly = self.line;
         }
     }
 
+    // Goes back to the start of line 0 from the given cycle
+    pub fn restart(&mut self, cycle: u64) {
+        self.line = 0;
+        self.ly = 0;
+        self.start = cycle;
+        self.timer = line_timer();
+    }
+
+    pub fn line(&self) -> u8 {
+        self.line
+    }
+
     pub fn ly(&self) -> u8 {
         self.ly
     }
 
+    // LY only reads as 153 briefly before reading 0 for the rest of the line
+    pub fn wrap_ly(&mut self) {
+        self.ly = 0;
+    }
+
+    pub fn is_last_line(&self) -> bool {
+        u64::from(self.line) == TOTAL_SCANLINES - 1
+    }
+
     pub fn lyc(&self) -> u8 {
         self.lyc
     }
@@ -57,16 +79,8 @@ impl ScanlineSweeper {
         }
     }
 
-    pub fn update_stat(&mut self, flags: u8) {
-        self.interrupt_enabled = (flags & LYC_MATCH_INT_FLAG) != 0;
-    }
-
-    pub fn timer(&self) -> Timer {
-        self.timer
-    }
-
     pub fn on_visible_scanline(&self) -> bool {
-        (self.ly as usize) < super::fb::SCREEN_SIZE.1
+        (self.line as usize) < super::fb::SCREEN_SIZE.1
     }
 }
 
@@ -92,14 +106,41 @@ fn test_lyc() {
     let mut sweeper = ScanlineSweeper::new();
     sweeper.set_lyc(42);
     for scanline in 0..42 {
+        sweeper.pump_cycle(LINE_CYCLE_TIME * scanline);
         assert_eq!(sweeper.stat_flags(), 0);
-        assert_eq!(sweeper.pump_cycle(LINE_CYCLE_TIME * scanline), None);
     }
 
-    sweeper.update_stat(LYC_MATCH_INT_FLAG);
-    assert_eq!(
-        sweeper.pump_cycle(LINE_CYCLE_TIME * 42),
-        Some(Interrupt::LCDC)
-    );
+    sweeper.pump_cycle(LINE_CYCLE_TIME * 42);
+    assert_eq!(sweeper.stat_flags(), LYC_MATCH_FLAG);
+}
+
+#[test]
+fn test_ly_wraps_early() {
+    let mut sweeper = ScanlineSweeper::new();
+    for scanline in 1..TOTAL_SCANLINES {
+        sweeper.pump_cycle(LINE_CYCLE_TIME * scanline);
+    }
+    assert!(sweeper.is_last_line());
+    assert_eq!(sweeper.ly(), 153);
+
+    sweeper.wrap_ly();
+    assert_eq!(sweeper.ly(), 0);
+    assert_eq!(sweeper.line(), 153);
     assert_eq!(sweeper.stat_flags(), LYC_MATCH_FLAG);
 }
+
+#[test]
+fn test_restart() {
+    let mut sweeper = ScanlineSweeper::new();
+    for scanline in 1..=3 {
+        sweeper.pump_cycle(LINE_CYCLE_TIME * scanline);
+    }
+    assert_eq!(sweeper.ly(), 3);
+
+    sweeper.restart(LINE_CYCLE_TIME * 3 + 100);
+    assert_eq!(sweeper.ly(), 0);
+    sweeper.pump_cycle(LINE_CYCLE_TIME * 4 + 99);
+    assert_eq!(sweeper.ly(), 0);
+    sweeper.pump_cycle(LINE_CYCLE_TIME * 4 + 100);
+    assert_eq!(sweeper.ly(), 1);
+}
@@ -8,10 +8,12 @@ pub mod debug;
 mod input;
 mod inst;
 mod lcd;
+mod link;
 mod mbc;
 mod mem;
 mod mmu;
 mod mmu_exceptions;
+mod serial;
 mod system;
 mod timer;
 
@@ -19,5 +21,9 @@ pub use crate::{
     audio::{AudioSink, NullSink},
     input::Button,
     lcd::fb::{Framebuffer, SCREEN_SIZE},
+    link::LinkedPair,
+    mbc::camera::{CameraSource, StillImageSource, TestPatternSource, CAMERA_HEIGHT, CAMERA_WIDTH},
+    mbc::rtc::RtcClock,
+    serial::{NullSerialDevice, SerialDevice, SocketLink},
     system::System,
 };
@@ -0,0 +1,162 @@
+use std::cmp::min;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{cpu::duration_to_cycle_count, serial::SerialDevice, system::System};
+
+// Neither console is allowed to run further ahead of the other than this
+const LOCKSTEP_CYCLES: u64 = 64;
+
+#[derive(Default)]
+struct Wire {
+    waiting: [Option<u8>; 2],
+    delivered: [Option<u8>; 2],
+}
+
+struct LinkEndpoint {
+    side: usize,
+    wire: Arc<Mutex<Wire>>,
+}
+
+impl SerialDevice for LinkEndpoint {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut wire = self.wire.lock().unwrap();
+        let peer = 1 - self.side;
+        if let Some(v) = wire.waiting[peer].take() {
+            wire.delivered[peer] = Some(out);
+            v
+        } else {
+            0xFF
+        }
+    }
+
+    fn poll_external(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.lock().unwrap();
+        if let Some(v) = wire.delivered[self.side].take() {
+            wire.waiting[self.side] = None;
+            Some(v)
+        } else {
+            wire.waiting[self.side] = Some(out);
+            None
+        }
+    }
+}
+
+pub struct LinkedPair {
+    systems: [System; 2],
+    wire: Arc<Mutex<Wire>>,
+}
+
+impl LinkedPair {
+    pub fn new(mut first: System, mut second: System) -> LinkedPair {
+        let wire = Arc::new(Mutex::new(Wire::default()));
+        first.attach_serial_device(Box::new(LinkEndpoint {
+            side: 0,
+            wire: wire.clone(),
+        }));
+        second.attach_serial_device(Box::new(LinkEndpoint {
+            side: 1,
+            wire: wire.clone(),
+        }));
+
+        LinkedPair {
+            systems: [first, second],
+            wire,
+        }
+    }
+
+    pub fn first(&mut self) -> &mut System {
+        &mut self.systems[0]
+    }
+
+    pub fn second(&mut self) -> &mut System {
+        &mut self.systems[1]
+    }
+
+    pub fn run_for_duration(&mut self, duration: &Duration) {
+        let cycles_to_run = duration_to_cycle_count(duration);
+        let stop_at_cycle = self.cycle() + cycles_to_run;
+
+        while self.cycle() < stop_at_cycle && !self.is_debug_halted() {
+            let slice_end = min(self.cycle() + LOCKSTEP_CYCLES, stop_at_cycle);
+            for side in 0..2 {
+                // A stale offer from a transfer the game has since cancelled
+                // must not be picked up; it is renewed on the next poll.
+                self.wire.lock().unwrap().waiting[side] = None;
+                self.systems[side].run_until_cycle(slice_end);
+            }
+        }
+    }
+
+    fn cycle(&self) -> u64 {
+        min(self.systems[0].cycle(), self.systems[1].cycle())
+    }
+
+    fn is_debug_halted(&mut self) -> bool {
+        self.systems
+            .iter_mut()
+            .any(|s| s.debugger().is_halted_on_debugger())
+    }
+
+    pub fn unlink(mut self) -> (System, System) {
+        for system in self.systems.iter_mut() {
+            system.detach_serial_device();
+        }
+        let [first, second] = self.systems;
+        (first, second)
+    }
+}
+
+#[cfg(test)]
+fn make_test_system(program: &[u8]) -> System {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    System::new(
+        std::io::Cursor::new(rom),
+        Box::new(crate::NullSink),
+        false,
+        None,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_linked_transfer() {
+    use crate::debug::Address;
+
+    // ld a, $42; ldh [SB], a; ld a, $81; ldh [SC], a; jr @
+    let master = make_test_system(&[0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+    // ld a, $99; ldh [SB], a; ld a, $80; ldh [SC], a; jr @
+    let slave = make_test_system(&[0x3E, 0x99, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE]);
+
+    let mut pair = LinkedPair::new(master, slave);
+    pair.run_for_duration(&Duration::from_millis(5));
+
+    assert_eq!(pair.first().debugger().read_mem(Address(0xFF01)), Ok(0x99));
+    assert_eq!(pair.second().debugger().read_mem(Address(0xFF01)), Ok(0x42));
+    assert_eq!(pair.first().debugger().read_mem(Address(0xFF02)), Ok(0x7F));
+    assert_eq!(pair.second().debugger().read_mem(Address(0xFF02)), Ok(0x7E));
+
+    let (mut master, _) = pair.unlink();
+    assert_eq!(master.debugger().read_mem(Address(0xFF01)), Ok(0x99));
+}
+
+#[test]
+fn test_linked_transfer_while_stopped() {
+    use crate::debug::Address;
+
+    // ld a, $42; ldh [SB], a; ld a, $81; ldh [SC], a; jr @
+    let master = make_test_system(&[0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+    // ld a, $99; ldh [SB], a; ld a, $80; ldh [SC], a; stop
+    let slave = make_test_system(&[0x3E, 0x99, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x10, 0x00]);
+
+    let mut pair = LinkedPair::new(master, slave);
+    pair.run_for_duration(&Duration::from_millis(5));
+
+    // The serial port is clocked by the master, so it still shifts in STOP
+    let cycles = duration_to_cycle_count(&Duration::from_millis(5));
+    assert!(pair.second().cycle() >= cycles);
+    assert_eq!(pair.first().debugger().read_mem(Address(0xFF01)), Ok(0x99));
+    assert_eq!(pair.second().debugger().read_mem(Address(0xFF01)), Ok(0x42));
+    assert_eq!(pair.second().debugger().read_mem(Address(0xFF02)), Ok(0x7E));
+}
@@ -1,12 +1,41 @@
+pub mod camera;
+pub mod huc1;
+pub mod huc3;
 pub mod mbc0;
 pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
 pub mod mbc5;
+pub mod mbc7;
+pub mod rtc;
 
+use self::camera::CameraSource;
+use self::rtc::RtcClock;
 use super::mem::{Address, ExtendedAddress, MemDevice};
 
 pub trait Mbc: MemDevice {
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress;
+    fn map_address_into_rom_bank0(&self, a: Address) -> ExtendedAddress {
+        ExtendedAddress(u32::from(a.0))
+    }
 
     fn get_sram(&self) -> &[u8];
     fn set_sram(&mut self, buf: &[u8]);
+
+    fn pump_cycle(&mut self, _cycle: u64) {}
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource + Send>) {}
+}
+
+// A ROM where the first byte of each bank is that bank's number
+#[cfg(test)]
+pub(super) fn make_test_rom(banks: usize) -> Vec<u8> {
+    use crate::mem::RNG_ROM_BANK1;
+
+    let mut rom = vec![0; RNG_ROM_BANK1.len() * banks];
+    for (i, bank) in rom.chunks_mut(RNG_ROM_BANK1.len()).enumerate() {
+        bank[0] = i as u8;
+    }
+    rom
 }
@@ -0,0 +1,367 @@
+mod source;
+
+use log::error;
+
+pub use self::source::{
+    CameraSource, StillImageSource, TestPatternSource, CAMERA_HEIGHT, CAMERA_WIDTH,
+};
+use super::Mbc;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_RAMG: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAM_BANK_SELECT: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_UNUSED: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+
+// Selecting this "RAM bank" maps the camera's registers into A000-BFFF
+const RAM_BANK_CAMERA: u8 = 0b0001_0000;
+
+const CAMERA_REG_COUNT: usize = 0x36;
+const REG_CONTROL: usize = 0x00;
+const REG_EDGE_MODE: usize = 0x01;
+const REG_EXPOSURE_HIGH: usize = 0x02;
+const REG_EXPOSURE_LOW: usize = 0x03;
+const REG_EDGE_RATIO: usize = 0x04;
+const REG_DITHER_START: usize = 0x06;
+
+const CONTROL_CAPTURE: u8 = 0b0000_0001;
+const CONTROL_WRITABLE: u8 = 0b0000_0111;
+const EDGE_MODE_SHIFT: u8 = 5;
+const EDGE_RATIO_SHIFT: u8 = 4;
+const EDGE_RATIO_INVERT: u8 = 0b0000_1000;
+
+const EDGE_MODE_NONE: u8 = 0;
+const EDGE_MODE_HORIZONTAL: u8 = 1;
+const EDGE_MODE_VERTICAL: u8 = 2;
+const EDGE_MODE_2D: u8 = 3;
+// Edge enhancement ratios, in quarters
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+// An exposure of 0x1000 passes the sensor's value through unchanged
+const EXPOSURE_UNITY: i32 = 0x1000;
+const CAPTURE_CYCLES: u64 = 129_792;
+const CAPTURE_CYCLES_PER_EXPOSURE: u64 = 64;
+
+// The captured image is written to the first RAM bank as tiles
+const OFF_IMAGE: usize = 0x100;
+
+pub struct PocketCamera {
+    ram_writable: bool,
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_bank_select: u8,
+    ram: Ram,
+
+    regs: [u8; CAMERA_REG_COUNT],
+    source: Box<dyn CameraSource + Send>,
+    cycle: u64,
+    capture_done_cycle: Option<u64>,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> PocketCamera {
+        PocketCamera {
+            ram_writable: false,
+            rom,
+            rom_bank_select: 1,
+            ram_bank_select: 0,
+            ram: Ram::new(ram_size),
+
+            regs: [0; CAMERA_REG_COUNT],
+            source: Box::new(TestPatternSource::default()),
+            cycle: 0,
+            capture_done_cycle: None,
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn map_address_into_ram(&self, a: Address) -> Option<usize> {
+        if self.ram.data.is_empty() {
+            return None;
+        }
+        let offset =
+            (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * self.ram_bank_select as usize;
+        Some(offset % self.ram.data.len())
+    }
+
+    fn camera_selected(&self) -> bool {
+        self.ram_bank_select & RAM_BANK_CAMERA != 0
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from(self.regs[REG_EXPOSURE_HIGH]) << 8 | u16::from(self.regs[REG_EXPOSURE_LOW])
+    }
+
+    fn start_capture(&mut self) {
+        let cycles = CAPTURE_CYCLES + u64::from(self.exposure()) * CAPTURE_CYCLES_PER_EXPOSURE;
+        self.capture_done_cycle = Some(self.cycle + cycles);
+    }
+
+    fn finish_capture(&mut self) {
+        let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.source.capture(&mut image);
+        let processed = self.process(&image);
+
+        let mut tiles = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT / 4];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let color = self.quantize(processed[y * CAMERA_WIDTH + x], x, y);
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                tiles[offset] |= (color & 0b01) << bit;
+                tiles[offset + 1] |= ((color & 0b10) >> 1) << bit;
+            }
+        }
+        if let Some(dst) = self.ram.data.get_mut(OFF_IMAGE..OFF_IMAGE + tiles.len()) {
+            dst.copy_from_slice(&tiles);
+        }
+
+        self.regs[REG_CONTROL] &= !CONTROL_CAPTURE;
+        self.capture_done_cycle = None;
+    }
+
+    // Runs the sensor's analog processing: exposure, inversion and edge
+    // enhancement
+    fn process(&self, image: &[u8]) -> Vec<i32> {
+        let exposure = i32::from(self.exposure());
+        let invert = self.regs[REG_EDGE_RATIO] & EDGE_RATIO_INVERT != 0;
+        let exposed: Vec<i32> = image
+            .iter()
+            .map(|p| {
+                let v = (i32::from(*p) * exposure / EXPOSURE_UNITY).min(255);
+                if invert {
+                    255 - v
+                } else {
+                    v
+                }
+            })
+            .collect();
+
+        let pixel = |x: isize, y: isize| {
+            let x = x.max(0).min(CAMERA_WIDTH as isize - 1) as usize;
+            let y = y.max(0).min(CAMERA_HEIGHT as isize - 1) as usize;
+            exposed[y * CAMERA_WIDTH + x]
+        };
+        let mode = (self.regs[REG_EDGE_MODE] >> EDGE_MODE_SHIFT) & 0b11;
+        let ratio = EDGE_RATIOS[((self.regs[REG_EDGE_RATIO] >> EDGE_RATIO_SHIFT) & 0b111) as usize];
+
+        let mut processed = Vec::with_capacity(exposed.len());
+        for y in 0..CAMERA_HEIGHT as isize {
+            for x in 0..CAMERA_WIDTH as isize {
+                let v = pixel(x, y);
+                let edge = match mode {
+                    EDGE_MODE_NONE => 0,
+                    EDGE_MODE_HORIZONTAL => 2 * v - pixel(x - 1, y) - pixel(x + 1, y),
+                    EDGE_MODE_VERTICAL => 2 * v - pixel(x, y - 1) - pixel(x, y + 1),
+                    EDGE_MODE_2D => {
+                        4 * v
+                            - pixel(x - 1, y)
+                            - pixel(x + 1, y)
+                            - pixel(x, y - 1)
+                            - pixel(x, y + 1)
+                    }
+                    _ => unreachable!(),
+                };
+                processed.push(v + edge * ratio / 4);
+            }
+        }
+        processed
+    }
+
+    // Compares against the 4x4 dither matrix's three thresholds for this
+    // pixel, giving a 2 bit shade where 3 is black
+    fn quantize(&self, v: i32, x: usize, y: usize) -> u8 {
+        let start = REG_DITHER_START + ((y % 4) * 4 + (x % 4)) * 3;
+        let thresholds = &self.regs[start..start + 3];
+        if v < i32::from(thresholds[0]) {
+            3
+        } else if v < i32::from(thresholds[1]) {
+            2
+        } else if v < i32::from(thresholds[2]) {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl MemDevice for PocketCamera {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            if self.camera_selected() {
+                // Only the control register can be read back
+                if (a - RNG_EXT_RAM.0).0 as usize % 0x80 == REG_CONTROL {
+                    Ok(self.regs[REG_CONTROL])
+                } else {
+                    Ok(0x00)
+                }
+            } else if self.capture_done_cycle.is_some() {
+                // The sensor has the RAM while it is capturing
+                Ok(0x00)
+            } else if let Some(index) = self.map_address_into_ram(a) {
+                Ok(self.ram.data[index])
+            } else {
+                Ok(0xFF)
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.camera_selected() {
+                let reg = (a - RNG_EXT_RAM.0).0 as usize % 0x80;
+                if reg == REG_CONTROL {
+                    let capturing = self.regs[REG_CONTROL] & CONTROL_CAPTURE != 0;
+                    self.regs[REG_CONTROL] = v & CONTROL_WRITABLE;
+                    if v & CONTROL_CAPTURE != 0 && !capturing {
+                        self.start_capture();
+                    } else if v & CONTROL_CAPTURE == 0 {
+                        self.capture_done_cycle = None;
+                    }
+                } else if reg < CAMERA_REG_COUNT {
+                    self.regs[reg] = v;
+                }
+                Ok(())
+            } else if !self.ram_writable {
+                error!("Error: RAM is not writable right now");
+                Err(())
+            } else {
+                if let Some(index) = self.map_address_into_ram(a) {
+                    self.ram.data[index] = v;
+                }
+                Ok(())
+            }
+        } else if a.in_(RNG_RAMG) {
+            self.ram_writable = v & 0b1111 == 0x0A;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & 0b0011_1111) as usize;
+            Ok(())
+        } else if a.in_(RNG_RAM_BANK_SELECT) {
+            self.ram_bank_select = v & 0b0001_1111;
+            Ok(())
+        } else if a.in_(RNG_UNUSED) {
+            Ok(())
+        } else {
+            error!("Unimplemented camera register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % self.rom_bank_count();
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        let len = buf.len().min(self.ram.data.len());
+        self.ram.data[..len].clone_from_slice(&buf[..len]);
+    }
+
+    fn pump_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+        if let Some(done) = self.capture_done_cycle {
+            if cycle >= done {
+                self.finish_capture();
+            }
+        }
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource + Send>) {
+        self.source = source;
+    }
+}
+
+#[cfg(test)]
+struct FlatSource(u8);
+
+#[cfg(test)]
+impl CameraSource for FlatSource {
+    fn capture(&mut self, image: &mut [u8]) {
+        for p in image.iter_mut() {
+            *p = self.0;
+        }
+    }
+}
+
+#[cfg(test)]
+fn make_test_camera(level: u8) -> PocketCamera {
+    let mut camera = PocketCamera::new(vec![0; RNG_ROM_BANK1.len() * 4], RNG_EXT_RAM.len() * 16);
+    camera.set_camera_source(Box::new(FlatSource(level)));
+    camera.write(Address(0x4000), RAM_BANK_CAMERA).unwrap();
+    camera
+        .write(Address(0xA000 + REG_EXPOSURE_HIGH as u16), 0x10)
+        .unwrap();
+    for i in 0..16 {
+        let reg = 0xA000 + (REG_DITHER_START + i * 3) as u16;
+        camera.write(Address(reg), 0x40).unwrap();
+        camera.write(Address(reg + 1), 0x80).unwrap();
+        camera.write(Address(reg + 2), 0xC0).unwrap();
+    }
+    camera
+}
+
+#[cfg(test)]
+fn capture(camera: &mut PocketCamera) {
+    camera.write(Address(0xA000), CONTROL_CAPTURE).unwrap();
+    assert_eq!(camera.read(Address(0xA000)), Ok(CONTROL_CAPTURE));
+    camera.pump_cycle(CAPTURE_CYCLES + 0x1000 * CAPTURE_CYCLES_PER_EXPOSURE);
+    assert_eq!(camera.read(Address(0xA000)), Ok(0));
+    camera.write(Address(0x4000), 0).unwrap();
+}
+
+#[test]
+fn test_camera_capture() {
+    let mut camera = make_test_camera(0x90);
+    capture(&mut camera);
+    // 0x90 lands between the second and third thresholds, shade 1
+    assert_eq!(camera.read(Address(0xA100)), Ok(0xFF));
+    assert_eq!(camera.read(Address(0xA101)), Ok(0x00));
+    assert_eq!(camera.read(Address(0xAEFF)), Ok(0x00));
+
+    // Halving the exposure darkens it to shade 2
+    let mut camera = make_test_camera(0x90);
+    camera
+        .write(Address(0xA000 + REG_EXPOSURE_HIGH as u16), 0x08)
+        .unwrap();
+    capture(&mut camera);
+    assert_eq!(camera.read(Address(0xA100)), Ok(0x00));
+    assert_eq!(camera.read(Address(0xA101)), Ok(0xFF));
+}
+
+#[test]
+fn test_camera_edge_enhancement() {
+    let mut camera = make_test_camera(0x90);
+    let image: Vec<u8> = (0..CAMERA_WIDTH * CAMERA_HEIGHT)
+        .map(|i| if i % CAMERA_WIDTH < 64 { 0x40 } else { 0xC0 })
+        .collect();
+    camera
+        .write(Address(0xA001), EDGE_MODE_HORIZONTAL << EDGE_MODE_SHIFT)
+        .unwrap();
+    camera
+        .write(Address(0xA004), 2 << EDGE_RATIO_SHIFT)
+        .unwrap();
+
+    let processed = camera.process(&image);
+    assert_eq!(processed[62], 0x40);
+    assert_eq!(processed[63], 0x40 - 0x80);
+    assert_eq!(processed[64], 0xC0 + 0x80);
+    assert_eq!(processed[65], 0xC0);
+}
@@ -0,0 +1,124 @@
+use std::io::{self, Error, ErrorKind, Read};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+// Anything that can stand in for the camera's sensor. Each capture fills
+// `image` with CAMERA_WIDTH x CAMERA_HEIGHT greyscale pixels, row by row,
+// where 0 is black and 255 is white.
+pub trait CameraSource {
+    fn capture(&mut self, image: &mut [u8]);
+}
+
+// A procedurally generated pattern that moves a little with every capture,
+// so it is obvious when the camera is live.
+#[derive(Default)]
+pub struct TestPatternSource {
+    frame: usize,
+}
+
+impl CameraSource for TestPatternSource {
+    fn capture(&mut self, image: &mut [u8]) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let gradient = (x + y + self.frame) * 255 / (CAMERA_WIDTH + CAMERA_HEIGHT);
+                let checker = ((x / 16) + (y / 16)) % 2 == 0;
+                image[y * CAMERA_WIDTH + x] = if checker {
+                    gradient as u8
+                } else {
+                    255 - gradient as u8
+                };
+            }
+        }
+        self.frame = (self.frame + 1) % (CAMERA_WIDTH + CAMERA_HEIGHT);
+    }
+}
+
+pub struct StillImageSource {
+    image: Vec<u8>,
+}
+
+impl StillImageSource {
+    // Loads a binary greyscale PGM ("P5") image, scaled to the sensor's size
+    pub fn from_pgm<R: Read>(mut r: R) -> io::Result<StillImageSource> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let mut pos = 0;
+        let magic = pgm_token(&data, &mut pos)?;
+        if magic != "P5" {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a binary PGM image"));
+        }
+        let width = pgm_number(&data, &mut pos)?;
+        let height = pgm_number(&data, &mut pos)?;
+        let max = pgm_number(&data, &mut pos)?;
+        if width == 0 || height == 0 || max == 0 || max > 255 {
+            return Err(Error::new(ErrorKind::InvalidData, "Unsupported PGM image"));
+        }
+
+        // Exactly one whitespace character separates the header from the
+        // pixels
+        let pixels = data.get(pos + 1..).unwrap_or(&[]);
+        if pixels.len() < width * height {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+
+        let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let src = pixels[(y * height / CAMERA_HEIGHT) * width + x * width / CAMERA_WIDTH];
+                image[y * CAMERA_WIDTH + x] = (usize::from(src) * 255 / max) as u8;
+            }
+        }
+
+        Ok(StillImageSource { image })
+    }
+}
+
+impl CameraSource for StillImageSource {
+    fn capture(&mut self, image: &mut [u8]) {
+        image.copy_from_slice(&self.image);
+    }
+}
+
+fn pgm_token(data: &[u8], pos: &mut usize) -> io::Result<String> {
+    loop {
+        match data.get(*pos) {
+            Some(b'#') => {
+                while matches!(data.get(*pos), Some(c) if *c != b'\n') {
+                    *pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(Error::from(ErrorKind::UnexpectedEof)),
+        }
+    }
+
+    let start = *pos;
+    while matches!(data.get(*pos), Some(c) if !c.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Ok(String::from_utf8_lossy(&data[start..*pos]).into_owned())
+}
+
+fn pgm_number(data: &[u8], pos: &mut usize) -> io::Result<usize> {
+    pgm_token(data, pos)?
+        .parse()
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Malformed PGM header"))
+}
+
+#[test]
+fn test_still_image_from_pgm() {
+    let mut pgm = b"P5\n# a comment\n2 1\n15\n".to_vec();
+    pgm.extend_from_slice(&[0, 15]);
+    let mut source = StillImageSource::from_pgm(pgm.as_slice()).unwrap();
+
+    let mut image = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+    source.capture(&mut image);
+    assert_eq!(image[0], 0);
+    assert_eq!(image[CAMERA_WIDTH - 1], 255);
+    assert_eq!(image[(CAMERA_HEIGHT - 1) * CAMERA_WIDTH], 0);
+
+    assert!(StillImageSource::from_pgm(&b"P2\n2 1\n15\n0 15"[..]).is_err());
+}
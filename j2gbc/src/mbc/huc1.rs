@@ -0,0 +1,136 @@
+use log::error;
+
+use super::Mbc;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_IR_SELECT: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAM_BANK_SELECT: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_UNUSED: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+
+const MODE_IR: u8 = 0x0E;
+// There is nobody on the other end of the infrared port, so the receiver
+// never sees any light
+const IR_NO_LIGHT: u8 = 0xC0;
+
+pub struct Huc1 {
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_bank_select: usize,
+    ram: Ram,
+    ir_mode: bool,
+    ir_led: bool,
+}
+
+impl Huc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Huc1 {
+        Huc1 {
+            rom,
+            rom_bank_select: 1,
+            ram_bank_select: 0,
+            ram: Ram::new(ram_size),
+            ir_mode: false,
+            ir_led: false,
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn map_address_into_ram(&self, a: Address) -> Option<Address> {
+        if self.ram.data.is_empty() {
+            return None;
+        }
+        let offset = (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * self.ram_bank_select;
+        Some(Address((offset % self.ram.data.len()) as u16))
+    }
+}
+
+impl MemDevice for Huc1 {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            if self.ir_mode {
+                Ok(IR_NO_LIGHT)
+            } else if let Some(mapped) = self.map_address_into_ram(a) {
+                self.ram.read(mapped)
+            } else {
+                Ok(0xFF)
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.ir_mode {
+                self.ir_led = v & 0b1 != 0;
+                Ok(())
+            } else if let Some(mapped) = self.map_address_into_ram(a) {
+                self.ram.write(mapped, v)
+            } else {
+                Ok(())
+            }
+        } else if a.in_(RNG_IR_SELECT) {
+            self.ir_mode = v & 0b1111 == MODE_IR;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & 0b0011_1111) as usize;
+            if self.rom_bank_select == 0 {
+                self.rom_bank_select = 1;
+            }
+            Ok(())
+        } else if a.in_(RNG_RAM_BANK_SELECT) {
+            self.ram_bank_select = (v & 0b0000_0011) as usize;
+            Ok(())
+        } else if a.in_(RNG_UNUSED) {
+            Ok(())
+        } else {
+            error!("Unimplemented HuC1 register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for Huc1 {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % self.rom_bank_count();
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        let len = buf.len().min(self.ram.data.len());
+        self.ram.data[..len].clone_from_slice(&buf[..len]);
+    }
+}
+
+#[test]
+fn test_huc1_banking_and_ir() {
+    let mut mbc = Huc1::new(super::make_test_rom(8), RNG_EXT_RAM.len() * 4);
+
+    mbc.write(Address(0x2000), 6).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(6));
+
+    mbc.write(Address(0x4000), 1).unwrap();
+    mbc.write(Address(0xA010), 0x5A).unwrap();
+    assert_eq!(mbc.get_sram()[RNG_EXT_RAM.len() + 0x10], 0x5A);
+
+    mbc.write(Address(0x0000), MODE_IR).unwrap();
+    assert_eq!(mbc.read(Address(0xA010)), Ok(IR_NO_LIGHT));
+    mbc.write(Address(0xA000), 0x01).unwrap();
+    assert!(mbc.ir_led);
+    assert_eq!(mbc.get_sram()[RNG_EXT_RAM.len()], 0x00);
+
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    assert_eq!(mbc.read(Address(0xA010)), Ok(0x5A));
+}
@@ -0,0 +1,403 @@
+use log::{error, info};
+
+use super::rtc::{Rtc, RtcClock, RtcState};
+use super::Mbc;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_MODE_SELECT: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAM_BANK_SELECT: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_UNUSED: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+
+// The low nibble written to 0000-1FFF decides what A000-BFFF is connected to
+const MODE_RAM_READ_ONLY: u8 = 0x0;
+const MODE_RAM: u8 = 0xA;
+const MODE_RTC_COMMAND: u8 = 0xB;
+const MODE_RTC_RESPONSE: u8 = 0xC;
+const MODE_RTC_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8 = 0xE;
+
+const RTC_CMD_READ: u8 = 0x1;
+const RTC_CMD_WRITE: u8 = 0x2;
+const RTC_CMD_WRITE_INC: u8 = 0x3;
+const RTC_CMD_INDEX_LOW: u8 = 0x4;
+const RTC_CMD_INDEX_HIGH: u8 = 0x5;
+const RTC_CMD_EXTENDED: u8 = 0x6;
+
+const RTC_EXT_STATUS: u8 = 0x2;
+const RTC_EXT_TONE: u8 = 0xE;
+
+// Nibble addresses of the RTC's registers
+const RTC_MINUTES_START: u8 = 0x00;
+const RTC_DAYS_START: u8 = 0x03;
+const RTC_DAYS_END: u8 = 0x07;
+const RTC_ALARM_MINUTES_START: u8 = 0x58;
+const RTC_ALARM_DAYS_START: u8 = 0x5B;
+const RTC_ALARM_DAYS_END: u8 = 0x5F;
+const RTC_ALARM_ENABLE: u8 = 0x5F;
+
+const MINUTES_PER_DAY: u16 = 60 * 24;
+const RTC_SEMAPHORE_READY: u8 = 0x01;
+const IR_NO_LIGHT: u8 = 0xC0;
+
+// The RTC state is appended to the save: the minute of the day, the day
+// counter, the alarm minute, the alarm day and the alarm enable as little
+// endian u32s, then the save time.
+const RTC_REGS_SAVE_LEN: usize = 20;
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u16,
+    days: u16,
+    alarm_minutes: u16,
+    alarm_days: u16,
+    alarm_enabled: bool,
+}
+
+impl RtcRegisters {
+    fn read_nibble(&self, index: u8) -> Option<u8> {
+        if index < RTC_DAYS_START {
+            Some((self.minutes >> ((index - RTC_MINUTES_START) * 4)) as u8 & 0xF)
+        } else if index < RTC_DAYS_END {
+            Some((self.days >> ((index - RTC_DAYS_START) * 4)) as u8 & 0xF)
+        } else {
+            None
+        }
+    }
+
+    fn write_nibble(&mut self, index: u8, v: u8) -> bool {
+        fn set_nibble(reg: &mut u16, shift: u8, v: u8) {
+            *reg = (*reg & !(0xF << shift)) | (u16::from(v & 0xF) << shift);
+        }
+
+        if index < RTC_DAYS_START {
+            set_nibble(&mut self.minutes, (index - RTC_MINUTES_START) * 4, v);
+        } else if index < RTC_DAYS_END {
+            set_nibble(&mut self.days, (index - RTC_DAYS_START) * 4, v);
+        } else if (RTC_ALARM_MINUTES_START..RTC_ALARM_DAYS_START).contains(&index) {
+            set_nibble(
+                &mut self.alarm_minutes,
+                (index - RTC_ALARM_MINUTES_START) * 4,
+                v,
+            );
+        } else if (RTC_ALARM_DAYS_START..RTC_ALARM_DAYS_END).contains(&index) {
+            set_nibble(&mut self.alarm_days, (index - RTC_ALARM_DAYS_START) * 4, v);
+        } else if index == RTC_ALARM_ENABLE {
+            self.alarm_enabled = v & 0b1 != 0;
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+impl RtcState for RtcRegisters {
+    const SAVE_LEN: usize = RTC_REGS_SAVE_LEN;
+
+    fn advance(&mut self, seconds: u64) {
+        let total_seconds = u64::from(self.seconds) + seconds;
+        self.seconds = (total_seconds % 60) as u8;
+
+        let total_minutes = u64::from(self.minutes) + total_seconds / 60;
+        self.minutes = (total_minutes % u64::from(MINUTES_PER_DAY)) as u16;
+        let days = total_minutes / u64::from(MINUTES_PER_DAY);
+        self.days = (u64::from(self.days) + days) as u16;
+    }
+
+    fn save(&self, buf: &mut [u8]) {
+        let regs = [
+            u32::from(self.minutes),
+            u32::from(self.days),
+            u32::from(self.alarm_minutes),
+            u32::from(self.alarm_days),
+            u32::from(self.alarm_enabled),
+        ];
+        for (i, reg) in regs.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&reg.to_le_bytes());
+        }
+    }
+
+    fn load(&mut self, buf: &[u8]) {
+        let reg = |i: usize| u16::from_le_bytes([buf[i * 4], buf[i * 4 + 1]]);
+        self.seconds = 0;
+        self.minutes = reg(0) % MINUTES_PER_DAY;
+        self.days = reg(1);
+        self.alarm_minutes = reg(2);
+        self.alarm_days = reg(3);
+        self.alarm_enabled = reg(4) != 0;
+    }
+}
+
+pub struct Huc3 {
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_bank_select: usize,
+    ram: Ram,
+    ram_size: usize,
+    mode: u8,
+
+    rtc: Rtc<RtcRegisters>,
+    rtc_index: u8,
+    rtc_command: u8,
+    rtc_response: u8,
+
+    ir_led: bool,
+}
+
+impl Huc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Huc3 {
+        let mut mbc = Huc3 {
+            rom,
+            rom_bank_select: 1,
+            ram_bank_select: 0,
+            ram: Ram::new(ram_size + Rtc::<RtcRegisters>::SAVE_LEN),
+            ram_size,
+            mode: MODE_RAM_READ_ONLY,
+
+            rtc: Rtc::new(),
+            rtc_index: 0,
+            rtc_command: 0,
+            rtc_response: 0,
+
+            ir_led: false,
+        };
+        mbc.store_rtc();
+        mbc
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn map_address_into_ram(&self, a: Address) -> Option<usize> {
+        if self.ram_size == 0 {
+            return None;
+        }
+        let offset = (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * self.ram_bank_select;
+        Some(offset % self.ram_size)
+    }
+
+    fn update_rtc(&mut self) {
+        if self.rtc.update() {
+            self.store_rtc();
+        }
+    }
+
+    fn store_rtc(&mut self) {
+        self.rtc.save(&mut self.ram.data[self.ram_size..]);
+    }
+
+    fn execute_rtc_command(&mut self, v: u8) {
+        let command = (v >> 4) & 0b0111;
+        let arg = v & 0xF;
+        self.rtc_command = command;
+
+        match command {
+            RTC_CMD_READ => {
+                self.update_rtc();
+                self.rtc_response = self.rtc.state.read_nibble(self.rtc_index).unwrap_or(0);
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            }
+            RTC_CMD_WRITE | RTC_CMD_WRITE_INC => {
+                self.update_rtc();
+                if self.rtc_index < RTC_DAYS_END {
+                    self.rtc.timebase.reset_subsecond();
+                    self.rtc.state.seconds = 0;
+                }
+                if !self.rtc.state.write_nibble(self.rtc_index, arg) {
+                    error!("Unimplemented HuC3 RTC register {:#04x}", self.rtc_index);
+                }
+                self.store_rtc();
+                if command == RTC_CMD_WRITE_INC {
+                    self.rtc_index = self.rtc_index.wrapping_add(1);
+                }
+            }
+            RTC_CMD_INDEX_LOW => self.rtc_index = (self.rtc_index & 0xF0) | arg,
+            RTC_CMD_INDEX_HIGH => self.rtc_index = (self.rtc_index & 0x0F) | (arg << 4),
+            RTC_CMD_EXTENDED => match arg {
+                RTC_EXT_STATUS => self.rtc_response = 1,
+                // The piezo buzzer on the cartridge isn't emulated
+                RTC_EXT_TONE => info!("HuC3 tone generator triggered, not emulated"),
+                _ => {}
+            },
+            _ => error!("Unimplemented HuC3 RTC command {:#04x}", v),
+        }
+    }
+}
+
+impl MemDevice for Huc3 {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            match self.mode {
+                MODE_RAM_READ_ONLY | MODE_RAM => {
+                    if let Some(index) = self.map_address_into_ram(a) {
+                        Ok(self.ram.data[index])
+                    } else {
+                        Ok(0xFF)
+                    }
+                }
+                MODE_RTC_RESPONSE => Ok((self.rtc_command << 4) | self.rtc_response),
+                MODE_RTC_SEMAPHORE => Ok(RTC_SEMAPHORE_READY),
+                MODE_IR => Ok(IR_NO_LIGHT),
+                _ => Ok(0xFF),
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_EXT_RAM) {
+            match self.mode {
+                MODE_RAM => {
+                    if let Some(index) = self.map_address_into_ram(a) {
+                        self.ram.data[index] = v;
+                    }
+                    Ok(())
+                }
+                MODE_RTC_COMMAND => {
+                    self.execute_rtc_command(v);
+                    Ok(())
+                }
+                MODE_RTC_SEMAPHORE => Ok(()),
+                MODE_IR => {
+                    self.ir_led = v & 0b1 != 0;
+                    Ok(())
+                }
+                _ => {
+                    error!("Error: RAM is not writable right now");
+                    Err(())
+                }
+            }
+        } else if a.in_(RNG_MODE_SELECT) {
+            self.mode = v & 0b1111;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & 0b0111_1111) as usize;
+            if self.rom_bank_select == 0 {
+                self.rom_bank_select = 1;
+            }
+            Ok(())
+        } else if a.in_(RNG_RAM_BANK_SELECT) {
+            self.ram_bank_select = (v & 0b0000_0011) as usize;
+            Ok(())
+        } else if a.in_(RNG_UNUSED) {
+            Ok(())
+        } else {
+            error!("Unimplemented HuC3 register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for Huc3 {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % self.rom_bank_count();
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        let ram_len = buf.len().min(self.ram_size);
+        self.ram.data[..ram_len].clone_from_slice(&buf[..ram_len]);
+
+        if buf.len() >= self.ram_size + Rtc::<RtcRegisters>::SAVE_LEN {
+            self.rtc.load(&buf[self.ram_size..]);
+            self.store_rtc();
+        }
+    }
+
+    fn pump_cycle(&mut self, cycle: u64) {
+        if self.rtc.pump_cycle(cycle) {
+            self.store_rtc();
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc.set_clock(clock);
+        self.store_rtc();
+    }
+}
+
+#[cfg(test)]
+fn make_test_mbc() -> Huc3 {
+    let mut mbc = Huc3::new(vec![0; RNG_ROM_BANK1.len() * 8], RNG_EXT_RAM.len() * 4);
+    mbc.set_rtc_clock(RtcClock::Emulated);
+    mbc
+}
+
+#[cfg(test)]
+fn read_rtc_nibbles(mbc: &mut Huc3, start: u8, count: u8) -> u16 {
+    mbc.write(Address(0x0000), MODE_RTC_COMMAND).unwrap();
+    mbc.write(Address(0xA000), (RTC_CMD_INDEX_LOW << 4) | (start & 0xF))
+        .unwrap();
+    mbc.write(Address(0xA000), (RTC_CMD_INDEX_HIGH << 4) | (start >> 4))
+        .unwrap();
+
+    let mut v = 0;
+    for i in 0..count {
+        mbc.write(Address(0x0000), MODE_RTC_COMMAND).unwrap();
+        mbc.write(Address(0xA000), RTC_CMD_READ << 4).unwrap();
+        mbc.write(Address(0x0000), MODE_RTC_RESPONSE).unwrap();
+        let response = mbc.read(Address(0xA000)).unwrap();
+        assert_eq!(response >> 4, RTC_CMD_READ);
+        v |= u16::from(response & 0xF) << (i * 4);
+    }
+    v
+}
+
+#[test]
+fn test_huc3_rtc() {
+    use crate::cpu::CLOCK_RATE;
+
+    let mut mbc = make_test_mbc();
+    mbc.pump_cycle(CLOCK_RATE * 60 * (MINUTES_PER_DAY as u64 + 5));
+    assert_eq!(read_rtc_nibbles(&mut mbc, RTC_MINUTES_START, 3), 5);
+    assert_eq!(read_rtc_nibbles(&mut mbc, RTC_DAYS_START, 4), 1);
+
+    mbc.write(Address(0x0000), MODE_RTC_COMMAND).unwrap();
+    mbc.write(Address(0xA000), RTC_CMD_INDEX_LOW << 4).unwrap();
+    mbc.write(Address(0xA000), RTC_CMD_INDEX_HIGH << 4).unwrap();
+    for nibble in &[0x3, 0x2, 0x1] {
+        mbc.write(Address(0xA000), (RTC_CMD_WRITE_INC << 4) | nibble)
+            .unwrap();
+    }
+    assert_eq!(read_rtc_nibbles(&mut mbc, RTC_MINUTES_START, 3), 0x123);
+
+    mbc.write(Address(0x0000), MODE_RTC_SEMAPHORE).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(RTC_SEMAPHORE_READY));
+}
+
+#[test]
+fn test_huc3_ram_and_save() {
+    let mut mbc = make_test_mbc();
+    mbc.write(Address(0x4000), 2).unwrap();
+    assert!(mbc.write(Address(0xA000), 0x42).is_err());
+    mbc.write(Address(0x0000), MODE_RAM).unwrap();
+    mbc.write(Address(0xA000), 0x42).unwrap();
+    mbc.write(Address(0x0000), MODE_IR).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(IR_NO_LIGHT));
+
+    mbc.rtc.state.days = 300;
+    mbc.store_rtc();
+    let save = mbc.get_sram().to_vec();
+    assert_eq!(
+        save.len(),
+        RNG_EXT_RAM.len() * 4 + Rtc::<RtcRegisters>::SAVE_LEN
+    );
+
+    let mut restored = make_test_mbc();
+    restored.set_sram(&save);
+    restored.write(Address(0x4000), 2).unwrap();
+    assert_eq!(restored.read(Address(0xA000)), Ok(0x42));
+    assert_eq!(read_rtc_nibbles(&mut restored, RTC_DAYS_START, 4), 300);
+}
@@ -12,44 +12,105 @@ const RNG_CTRL_UPPER_BANK_SELECT: AddressRange = AddressRange(Address(0x6000), A
 const MAKS_UPPER_BANK_SELCET: u8 = 0b0000_0011;
 const MASK_LOWER_BANK_SELECT: u8 = 0b0001_1111;
 
+// MBC1M multicarts leave bit 4 of the lower bank register unconnected and
+// wire the upper bank bits one position lower
+const LOWER_BANK_BITS: usize = 5;
+const LOWER_BANK_BITS_MULTICART: usize = 4;
+const MULTICART_ROM_SIZE: usize = 0x10_0000;
+const MULTICART_GAME_BANKS: usize = 0x10;
+const OFF_LOGO_START: usize = 0x104;
+const OFF_LOGO_END: usize = 0x134;
+
 pub struct Mbc1 {
     ram_protected: bool,
     rom: Vec<u8>,
     lower_bank_select: usize,
+    lower_bank_bits: usize,
     upper_bank_controls_rom: bool,
     upper_bank_select: usize,
     ram: Ram,
 }
 
 impl Mbc1 {
-    pub fn new(rom: Vec<u8>) -> Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+        let lower_bank_bits = if is_multicart(&rom) {
+            LOWER_BANK_BITS_MULTICART
+        } else {
+            LOWER_BANK_BITS
+        };
+
         Mbc1 {
             ram_protected: true,
             rom,
+            lower_bank_bits,
             upper_bank_controls_rom: true,
             upper_bank_select: 0,
             lower_bank_select: 1,
-            ram: Ram::new(RNG_EXT_RAM.len() * 4),
+            ram: Ram::new(ram_size),
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn upper_bank_bits(&self) -> usize {
+        self.upper_bank_select << self.lower_bank_bits
+    }
+
+    fn rom_bank(&self) -> usize {
+        let lower = self.lower_bank_select & ((1 << self.lower_bank_bits) - 1);
+        (self.upper_bank_bits() | lower) % self.rom_bank_count()
+    }
+
+    fn rom_bank0(&self) -> usize {
+        if self.upper_bank_controls_rom {
+            0
+        } else {
+            self.upper_bank_bits() % self.rom_bank_count()
         }
     }
 
     fn map_address_into_ram(&self, a: Address) -> Address {
+        // Only carts with more than one RAM bank connect the upper bank bits
+        // to RAM; on smaller carts they are simply not wired up
         let bank = if !self.upper_bank_controls_rom {
             self.upper_bank_select
         } else {
             0
         };
-        Address(((a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank) as u16)
+        let offset = (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank;
+        Address((offset % self.ram.data.len()) as u16)
+    }
+
+    fn ram_accessible(&self) -> bool {
+        !self.ram_protected && !self.ram.data.is_empty()
     }
 }
 
+fn is_multicart(rom: &[u8]) -> bool {
+    // Each game on an MBC1M cart starts on a 256 KiB boundary, so the second
+    // game's header (with its own copy of the logo) sits at bank 0x10
+    if rom.len() != MULTICART_ROM_SIZE {
+        return false;
+    }
+
+    let second_game = MULTICART_GAME_BANKS * RNG_ROM_BANK1.len();
+    rom[OFF_LOGO_START..OFF_LOGO_END]
+        == rom[second_game + OFF_LOGO_START..second_game + OFF_LOGO_END]
+}
+
 impl MemDevice for Mbc1 {
     fn read(&self, a: Address) -> Result<u8, ()> {
         if a.in_(RNG_ROM_BANK1) {
             let index = self.map_address_into_rom(a).0 as usize;
             Ok(self.rom[index])
         } else if a.in_(RNG_EXT_RAM) {
-            self.ram.read(a - RNG_EXT_RAM.0)
+            if self.ram_accessible() {
+                self.ram.read(self.map_address_into_ram(a))
+            } else {
+                Ok(0xFF)
+            }
         } else {
             unreachable!();
         }
@@ -63,7 +124,7 @@ impl MemDevice for Mbc1 {
             }
             Ok(())
         } else if a.in_(RNG_EXT_RAM) {
-            if self.ram_protected {
+            if !self.ram_accessible() {
                 error!("Error: RAM is not writable right now");
                 Err(())
             } else {
@@ -71,13 +132,13 @@ impl MemDevice for Mbc1 {
                 self.ram.write(mapped, v)
             }
         } else if a.in_(RNG_RAMCS) {
-            self.ram_protected = v != 0x0A;
+            self.ram_protected = v & 0b1111 != 0x0A;
             Ok(())
         } else if a.in_(RNG_UPPER_BANK_SELECT) {
             self.upper_bank_select = (v & MAKS_UPPER_BANK_SELCET) as usize;
             Ok(())
         } else if a.in_(RNG_CTRL_UPPER_BANK_SELECT) {
-            self.upper_bank_controls_rom = v == 0;
+            self.upper_bank_controls_rom = v & 0b1 == 0;
             Ok(())
         } else {
             error!("Unimplemented MBC1 register");
@@ -88,20 +149,91 @@ impl MemDevice for Mbc1 {
 
 impl Mbc for Mbc1 {
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
-        if self.upper_bank_controls_rom && self.upper_bank_select != 0 {
-            unimplemented!();
-        }
-
         ExtendedAddress(
-            (RNG_ROM_BANK1.len() * (self.lower_bank_select - 1)) as u32 + u32::from(a.0),
+            (RNG_ROM_BANK1.len() * self.rom_bank()) as u32 + u32::from((a - RNG_ROM_BANK1.0).0),
         )
     }
 
+    fn map_address_into_rom_bank0(&self, a: Address) -> ExtendedAddress {
+        ExtendedAddress((RNG_ROM_BANK1.len() * self.rom_bank0()) as u32 + u32::from(a.0))
+    }
+
     fn get_sram(&self) -> &[u8] {
         self.ram.data.as_slice()
     }
 
     fn set_sram(&mut self, buf: &[u8]) {
-        self.ram.data[..buf.len()].clone_from_slice(buf);
+        let len = buf.len().min(self.ram.data.len());
+        self.ram.data[..len].clone_from_slice(&buf[..len]);
     }
 }
+
+#[test]
+fn test_mbc1_large_rom() {
+    let mut mbc = Mbc1::new(super::make_test_rom(128), 0);
+
+    mbc.write(Address(0x2000), 0x00).unwrap();
+    mbc.write(Address(0x4000), 0x02).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(0x41));
+    assert_eq!(mbc.map_address_into_rom_bank0(Address(0x0000)).0, 0);
+
+    // Mode 1 applies the upper bits to the 0000-3FFF window as well
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(
+        mbc.map_address_into_rom_bank0(Address(0x0000)).0,
+        0x40 * 0x4000
+    );
+
+    // Banks past the end of the ROM wrap around
+    let mut mbc = Mbc1::new(super::make_test_rom(8), 0);
+    mbc.write(Address(0x2000), 0x0B).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(0x03));
+}
+
+#[test]
+fn test_mbc1_ram_banking() {
+    let mut mbc = Mbc1::new(super::make_test_rom(4), RNG_EXT_RAM.len() * 4);
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0xFF));
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    mbc.write(Address(0x4000), 0x02).unwrap();
+    mbc.write(Address(0xA000), 0x12).unwrap();
+    assert_eq!(mbc.get_sram()[RNG_EXT_RAM.len() * 2], 0x12);
+
+    // Mode 0 always accesses the first RAM bank
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0x00));
+
+    // Small RAM does not see the upper bank bits
+    let mut mbc = Mbc1::new(super::make_test_rom(4), RNG_EXT_RAM.len());
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    mbc.write(Address(0x4000), 0x03).unwrap();
+    mbc.write(Address(0xA000), 0x34).unwrap();
+    assert_eq!(mbc.get_sram()[0], 0x34);
+}
+
+#[test]
+fn test_mbc1_multicart() {
+    let mut rom = super::make_test_rom(64);
+    for game in 0..4 {
+        let start = game * MULTICART_GAME_BANKS * RNG_ROM_BANK1.len();
+        for (i, b) in rom[start + OFF_LOGO_START..start + OFF_LOGO_END]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = i as u8 + 1;
+        }
+    }
+    let mut mbc = Mbc1::new(rom, 0);
+
+    mbc.write(Address(0x4000), 0x01).unwrap();
+    mbc.write(Address(0x2000), 0x12).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(0x12));
+
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(
+        mbc.map_address_into_rom_bank0(Address(0x0000)).0,
+        0x10 * 0x4000
+    );
+}
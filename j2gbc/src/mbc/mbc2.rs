@@ -0,0 +1,131 @@
+use log::error;
+
+use super::Mbc;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_REGS: AddressRange = AddressRange(Address(0x0000), Address(0x4000));
+const REG_SELECT_ROMB: u16 = 0b0000_0001_0000_0000;
+const RAM_SIZE: usize = 512;
+const RAM_UNUSED_BITS: u8 = 0b1111_0000;
+
+pub struct Mbc2 {
+    ram_protected: bool,
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram: Ram,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            ram_protected: true,
+            rom,
+            rom_bank_select: 1,
+            ram: Ram::new(RAM_SIZE),
+        }
+    }
+
+    fn map_address_into_ram(a: Address) -> Address {
+        // Only the low 9 address lines are connected, so the RAM echoes
+        // across the whole external RAM window
+        Address((a - RNG_EXT_RAM.0).0 % RAM_SIZE as u16)
+    }
+}
+
+impl MemDevice for Mbc2 {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                Ok(0xFF)
+            } else {
+                Ok(self.ram.read(Self::map_address_into_ram(a))? | RAM_UNUSED_BITS)
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                error!("Error: RAM is not writable right now");
+                Err(())
+            } else {
+                self.ram
+                    .write(Self::map_address_into_ram(a), v & !RAM_UNUSED_BITS)
+            }
+        } else if a.in_(RNG_REGS) {
+            if a.0 & REG_SELECT_ROMB == 0 {
+                self.ram_protected = v & 0b1111 != 0x0A;
+            } else {
+                self.rom_bank_select = (v & 0b1111) as usize;
+                if self.rom_bank_select == 0 {
+                    self.rom_bank_select = 1;
+                }
+            }
+            Ok(())
+        } else {
+            error!("Unimplemented MBC2 register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % (self.rom.len() / RNG_ROM_BANK1.len());
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        for (dst, src) in self.ram.data.iter_mut().zip(buf.iter()) {
+            *dst = src & !RAM_UNUSED_BITS;
+        }
+    }
+}
+
+#[test]
+fn test_mbc2_registers() {
+    let mut mbc = Mbc2::new(super::make_test_rom(4));
+
+    // Bit 8 set selects the ROM bank register
+    mbc.write(Address(0x2100), 3).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(3));
+    mbc.write(Address(0x0100), 0).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(1));
+
+    // Bit 8 clear selects RAM enable, regardless of the rest of the address
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0xFF));
+    mbc.write(Address(0x3E00), 0x0A).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(1));
+    mbc.write(Address(0xA000), 0x00).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0xF0));
+}
+
+#[test]
+fn test_mbc2_ram() {
+    let mut mbc = Mbc2::new(vec![0; RNG_ROM_BANK1.len() * 2]);
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+
+    mbc.write(Address(0xA005), 0xAB).unwrap();
+    assert_eq!(mbc.read(Address(0xA005)), Ok(0xFB));
+    assert_eq!(mbc.read(Address(0xA205)), Ok(0xFB));
+    assert_eq!(mbc.read(Address(0xBE05)), Ok(0xFB));
+
+    assert_eq!(mbc.get_sram().len(), RAM_SIZE);
+    assert_eq!(mbc.get_sram()[5], 0x0B);
+
+    let mut restored = Mbc2::new(vec![0; RNG_ROM_BANK1.len() * 2]);
+    restored.set_sram(mbc.get_sram());
+    restored.write(Address(0x0000), 0x0A).unwrap();
+    assert_eq!(restored.read(Address(0xA005)), Ok(0xFB));
+}
@@ -0,0 +1,439 @@
+use log::error;
+
+use super::rtc::{Rtc, RtcClock, RtcState};
+use super::Mbc;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_RAMG: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAM_BANK_SELECT: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_LATCH_CLOCK: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+
+const RTC_REG_SECONDS: u8 = 0x08;
+const RTC_REG_MINUTES: u8 = 0x09;
+const RTC_REG_HOURS: u8 = 0x0A;
+const RTC_REG_DAY_LOW: u8 = 0x0B;
+const RTC_REG_DAY_HIGH: u8 = 0x0C;
+
+const RTC_DAY_HIGH_BIT: u8 = 0b0000_0001;
+const RTC_HALT_FLAG: u8 = 0b0100_0000;
+const RTC_DAY_CARRY_FLAG: u8 = 0b1000_0000;
+
+// Same layout as the footer other emulators append to MBC3 saves: the live
+// and latched registers as little endian u32s, then the save time.
+const RTC_REGS_SAVE_LEN: usize = 20;
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+}
+
+impl RtcRegisters {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            RTC_REG_SECONDS => self.seconds,
+            RTC_REG_MINUTES => self.minutes,
+            RTC_REG_HOURS => self.hours,
+            RTC_REG_DAY_LOW => self.days as u8,
+            RTC_REG_DAY_HIGH => {
+                let mut v = (self.days >> 8) as u8 & RTC_DAY_HIGH_BIT;
+                if self.halted {
+                    v |= RTC_HALT_FLAG;
+                }
+                if self.day_carry {
+                    v |= RTC_DAY_CARRY_FLAG;
+                }
+                v
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u8, v: u8) {
+        match reg {
+            RTC_REG_SECONDS => self.seconds = v & 0b0011_1111,
+            RTC_REG_MINUTES => self.minutes = v & 0b0011_1111,
+            RTC_REG_HOURS => self.hours = v & 0b0001_1111,
+            RTC_REG_DAY_LOW => self.days = (self.days & 0x100) | u16::from(v),
+            RTC_REG_DAY_HIGH => {
+                self.days = (self.days & 0xFF) | (u16::from(v & RTC_DAY_HIGH_BIT) << 8);
+                self.halted = v & RTC_HALT_FLAG != 0;
+                self.day_carry = v & RTC_DAY_CARRY_FLAG != 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self) {
+        // Out of range values keep counting up to the width of the register
+        // before wrapping to 0, and only carry when they hit the real limit.
+        self.seconds = (self.seconds + 1) & 0b0011_1111;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0b0011_1111;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0b0001_1111;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        if self.halted {
+            return;
+        }
+
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+
+        let total = seconds
+            + u64::from(self.seconds)
+            + u64::from(self.minutes) * 60
+            + u64::from(self.hours) * 60 * 60
+            + u64::from(self.days) * 60 * 60 * 24;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / (60 * 60) % 24) as u8;
+        let days = total / (60 * 60 * 24);
+        if days >= 512 {
+            self.day_carry = true;
+        }
+        self.days = (days % 512) as u16;
+    }
+
+    fn save(&self, buf: &mut [u8]) {
+        for (i, reg) in (RTC_REG_SECONDS..=RTC_REG_DAY_HIGH).enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&u32::from(self.read(reg)).to_le_bytes());
+        }
+    }
+
+    fn load(&mut self, buf: &[u8]) {
+        for (i, reg) in (RTC_REG_SECONDS..=RTC_REG_DAY_HIGH).enumerate() {
+            self.write(reg, buf[i * 4]);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Mbc3RtcState {
+    live: RtcRegisters,
+    latched: RtcRegisters,
+}
+
+impl RtcState for Mbc3RtcState {
+    const SAVE_LEN: usize = RTC_REGS_SAVE_LEN * 2;
+
+    fn advance(&mut self, seconds: u64) {
+        self.live.advance(seconds);
+    }
+
+    fn save(&self, buf: &mut [u8]) {
+        self.live.save(&mut buf[..RTC_REGS_SAVE_LEN]);
+        self.latched.save(&mut buf[RTC_REGS_SAVE_LEN..]);
+    }
+
+    fn load(&mut self, buf: &[u8]) {
+        self.live.load(&buf[..RTC_REGS_SAVE_LEN]);
+        self.latched.load(&buf[RTC_REGS_SAVE_LEN..]);
+    }
+}
+
+pub struct Mbc3 {
+    ram_protected: bool,
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_bank_select: u8,
+    ram: Ram,
+    ram_size: usize,
+
+    has_rtc: bool,
+    rtc: Rtc<Mbc3RtcState>,
+    rtc_latch_armed: bool,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Mbc3 {
+        let save_size = if has_rtc {
+            ram_size + Rtc::<Mbc3RtcState>::SAVE_LEN
+        } else {
+            ram_size
+        };
+
+        let mut mbc = Mbc3 {
+            ram_protected: true,
+            rom,
+            rom_bank_select: 1,
+            ram_bank_select: 0,
+            ram: Ram::new(save_size),
+            ram_size,
+
+            has_rtc,
+            rtc: Rtc::new(),
+            rtc_latch_armed: false,
+        };
+        mbc.store_rtc();
+        mbc
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn selected_rtc_reg(&self) -> Option<u8> {
+        if self.has_rtc && (RTC_REG_SECONDS..=RTC_REG_DAY_HIGH).contains(&self.ram_bank_select) {
+            Some(self.ram_bank_select)
+        } else {
+            None
+        }
+    }
+
+    fn map_address_into_ram(&self, a: Address) -> Option<usize> {
+        // Selects past the RAM banks that aren't RTC registers are open bus
+        if self.ram_size == 0 || self.ram_bank_select >= RTC_REG_SECONDS {
+            return None;
+        }
+        let offset =
+            (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * self.ram_bank_select as usize;
+        Some(offset % self.ram_size)
+    }
+
+    fn update_rtc(&mut self) {
+        if self.rtc.update() {
+            self.store_rtc();
+        }
+    }
+
+    fn store_rtc(&mut self) {
+        if self.has_rtc {
+            self.rtc.save(&mut self.ram.data[self.ram_size..]);
+        }
+    }
+}
+
+impl MemDevice for Mbc3 {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                Ok(0xFF)
+            } else if let Some(reg) = self.selected_rtc_reg() {
+                Ok(self.rtc.state.latched.read(reg))
+            } else if let Some(index) = self.map_address_into_ram(a) {
+                Ok(self.ram.data[index])
+            } else {
+                Ok(0xFF)
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                error!("Error: RAM is not writable right now");
+                Err(())
+            } else if let Some(reg) = self.selected_rtc_reg() {
+                self.update_rtc();
+                if reg == RTC_REG_SECONDS {
+                    self.rtc.timebase.reset_subsecond();
+                }
+                self.rtc.state.live.write(reg, v);
+                self.rtc.state.latched.write(reg, v);
+                self.store_rtc();
+                Ok(())
+            } else if let Some(index) = self.map_address_into_ram(a) {
+                self.ram.data[index] = v;
+                Ok(())
+            } else {
+                Ok(())
+            }
+        } else if a.in_(RNG_RAMG) {
+            self.ram_protected = v & 0b1111 != 0x0A;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & 0b0111_1111) as usize;
+            if self.rom_bank_select == 0 {
+                self.rom_bank_select = 1;
+            }
+            Ok(())
+        } else if a.in_(RNG_RAM_BANK_SELECT) {
+            self.ram_bank_select = v & 0b0000_1111;
+            Ok(())
+        } else if a.in_(RNG_LATCH_CLOCK) {
+            if v == 0 {
+                self.rtc_latch_armed = true;
+            } else {
+                if v == 1 && self.rtc_latch_armed {
+                    self.update_rtc();
+                    self.rtc.state.latched = self.rtc.state.live;
+                    self.store_rtc();
+                }
+                self.rtc_latch_armed = false;
+            }
+            Ok(())
+        } else {
+            error!("Unimplemented MBC3 register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % self.rom_bank_count();
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        let ram_len = buf.len().min(self.ram_size);
+        self.ram.data[..ram_len].clone_from_slice(&buf[..ram_len]);
+
+        if self.has_rtc && buf.len() >= self.ram_size + Rtc::<Mbc3RtcState>::SAVE_LEN {
+            self.rtc.load(&buf[self.ram_size..]);
+            self.store_rtc();
+        }
+    }
+
+    fn pump_cycle(&mut self, cycle: u64) {
+        if self.has_rtc && self.rtc.pump_cycle(cycle) {
+            self.store_rtc();
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc.set_clock(clock);
+        self.store_rtc();
+    }
+}
+
+#[cfg(test)]
+fn make_test_mbc(has_rtc: bool) -> Mbc3 {
+    let mut mbc = Mbc3::new(super::make_test_rom(8), RNG_EXT_RAM.len() * 4, has_rtc);
+    mbc.set_rtc_clock(RtcClock::Emulated);
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc
+}
+
+#[test]
+fn test_mbc3_banking() {
+    let mut mbc = make_test_mbc(false);
+    assert_eq!(mbc.read(Address(0x4000)), Ok(1));
+    mbc.write(Address(0x2000), 0).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(1));
+    mbc.write(Address(0x2000), 5).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(5));
+    mbc.write(Address(0x2000), 13).unwrap();
+    assert_eq!(mbc.read(Address(0x4000)), Ok(5));
+
+    mbc.write(Address(0x4000), 2).unwrap();
+    mbc.write(Address(0xA123), 0x42).unwrap();
+    mbc.write(Address(0x4000), 3).unwrap();
+    assert_eq!(mbc.read(Address(0xA123)), Ok(0));
+    mbc.write(Address(0x4000), 2).unwrap();
+    assert_eq!(mbc.read(Address(0xA123)), Ok(0x42));
+    assert_eq!(mbc.get_sram()[RNG_EXT_RAM.len() * 2 + 0x123], 0x42);
+}
+
+#[test]
+fn test_mbc3_rtc_latch() {
+    use crate::cpu::CLOCK_RATE;
+
+    let mut mbc = make_test_mbc(true);
+    mbc.pump_cycle(CLOCK_RATE * (60 * 60 + 61));
+
+    mbc.write(Address(0x4000), RTC_REG_SECONDS).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0));
+
+    mbc.write(Address(0x6000), 0).unwrap();
+    mbc.write(Address(0x6000), 1).unwrap();
+    mbc.pump_cycle(CLOCK_RATE * (60 * 60 + 70));
+    assert_eq!(mbc.read(Address(0xA000)), Ok(1));
+    mbc.write(Address(0x4000), RTC_REG_MINUTES).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(1));
+    mbc.write(Address(0x4000), RTC_REG_HOURS).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(1));
+}
+
+#[test]
+fn test_mbc3_rtc_day_carry() {
+    let mut rtc = RtcRegisters::default();
+    rtc.write(RTC_REG_DAY_HIGH, RTC_DAY_HIGH_BIT);
+    rtc.write(RTC_REG_DAY_LOW, 0xFF);
+    rtc.write(RTC_REG_HOURS, 23);
+    rtc.write(RTC_REG_MINUTES, 59);
+    rtc.write(RTC_REG_SECONDS, 59);
+    rtc.advance(1);
+    assert_eq!(rtc.read(RTC_REG_DAY_LOW), 0);
+    assert_eq!(rtc.read(RTC_REG_DAY_HIGH), RTC_DAY_CARRY_FLAG);
+
+    rtc.write(RTC_REG_DAY_HIGH, RTC_HALT_FLAG);
+    rtc.advance(100);
+    assert_eq!(rtc.read(RTC_REG_SECONDS), 0);
+
+    rtc.write(RTC_REG_DAY_HIGH, 0);
+    rtc.write(RTC_REG_SECONDS, 62);
+    rtc.advance(3);
+    assert_eq!(rtc.read(RTC_REG_SECONDS), 1);
+    assert_eq!(rtc.read(RTC_REG_MINUTES), 0);
+}
+
+#[test]
+fn test_mbc3_rtc_save() {
+    let mut mbc = make_test_mbc(true);
+    mbc.write(Address(0x4000), RTC_REG_HOURS).unwrap();
+    mbc.write(Address(0xA000), 13).unwrap();
+    mbc.write(Address(0x4000), 0).unwrap();
+    mbc.write(Address(0xA000), 0x99).unwrap();
+
+    let save = mbc.get_sram().to_vec();
+    assert_eq!(
+        save.len(),
+        RNG_EXT_RAM.len() * 4 + Rtc::<Mbc3RtcState>::SAVE_LEN
+    );
+
+    let mut restored = make_test_mbc(true);
+    restored.set_sram(&save);
+    assert_eq!(restored.read(Address(0xA000)), Ok(0x99));
+    restored.write(Address(0x4000), RTC_REG_HOURS).unwrap();
+    assert_eq!(restored.read(Address(0xA000)), Ok(13));
+}
+
+#[test]
+fn test_mbc3_unmapped_select() {
+    let mut mbc = make_test_mbc(true);
+    mbc.write(Address(0xA000), 0x42).unwrap();
+
+    mbc.write(Address(0x4000), 0x0D).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0xFF));
+    mbc.write(Address(0xA000), 0x12).unwrap();
+
+    mbc.write(Address(0x4000), 0).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)), Ok(0x42));
+}
@@ -0,0 +1,422 @@
+use log::error;
+
+use super::Mbc;
+use crate::mem::{Address, AddressRange, ExtendedAddress, MemDevice, RNG_EXT_RAM, RNG_ROM_BANK1};
+
+const RNG_RAMG1: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAMG2: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_UNUSED: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+const RNG_REGS: AddressRange = AddressRange(Address(0xA000), Address(0xB000));
+
+// Registers are selected by bits 4-7 of the address
+const REG_LATCH_ERASE: u16 = 0x0;
+const REG_LATCH: u16 = 0x1;
+const REG_ACCEL_X_LOW: u16 = 0x2;
+const REG_ACCEL_X_HIGH: u16 = 0x3;
+const REG_ACCEL_Y_LOW: u16 = 0x4;
+const REG_ACCEL_Y_HIGH: u16 = 0x5;
+const REG_ZERO: u16 = 0x6;
+const REG_EEPROM: u16 = 0x8;
+
+const LATCH_ERASE_VALUE: u8 = 0x55;
+const LATCH_VALUE: u8 = 0xAA;
+const ACCEL_ERASED: u16 = 0x8000;
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+
+const EEPROM_CS: u8 = 0b1000_0000;
+const EEPROM_CLK: u8 = 0b0100_0000;
+const EEPROM_DI: u8 = 0b0000_0010;
+const EEPROM_DO: u8 = 0b0000_0001;
+
+const EEPROM_SIZE: usize = 256;
+// Start bit, 2 opcode bits and 8 address bits
+const EEPROM_COMMAND_BITS: u8 = 11;
+const EEPROM_WORD_BITS: u8 = 16;
+
+const EEPROM_OP_EXTENDED: u16 = 0b00;
+const EEPROM_OP_WRITE: u16 = 0b01;
+const EEPROM_OP_READ: u16 = 0b10;
+const EEPROM_OP_ERASE: u16 = 0b11;
+const EEPROM_EXT_EWDS: u16 = 0b00;
+const EEPROM_EXT_WRAL: u16 = 0b01;
+const EEPROM_EXT_ERAL: u16 = 0b10;
+const EEPROM_EXT_EWEN: u16 = 0b11;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EepromState {
+    Command,
+    Read,
+    Write(Option<u8>),
+}
+
+// A 93LC56 serial EEPROM in its 128 x 16 bit organization. Words are stored
+// little endian so the save file is just the EEPROM contents.
+struct Eeprom {
+    data: Vec<u8>,
+    write_enabled: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+
+    state: EepromState,
+    shift: u16,
+    bits: u8,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: vec![0xFF; EEPROM_SIZE],
+            write_enabled: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+
+            state: EepromState::Command,
+            shift: 0,
+            bits: 0,
+        }
+    }
+
+    fn read_word(&self, address: u8) -> u16 {
+        let i = address as usize * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+
+    fn write_word(&mut self, address: u8, v: u16) {
+        if self.write_enabled {
+            let i = address as usize * 2;
+            self.data[i..i + 2].copy_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let mut v = 0;
+        if self.cs {
+            v |= EEPROM_CS;
+        }
+        if self.clk {
+            v |= EEPROM_CLK;
+        }
+        if self.di {
+            v |= EEPROM_DI;
+        }
+        if self.do_ {
+            v |= EEPROM_DO;
+        }
+        v
+    }
+
+    fn write(&mut self, v: u8) {
+        let cs = v & EEPROM_CS != 0;
+        let clk = v & EEPROM_CLK != 0;
+        self.di = v & EEPROM_DI != 0;
+
+        if !cs {
+            self.state = EepromState::Command;
+            self.shift = 0;
+            self.bits = 0;
+        } else if !self.clk && clk {
+            self.clock_bit();
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn clock_bit(&mut self) {
+        match self.state {
+            EepromState::Command => {
+                // Wait for the start bit
+                if self.bits == 0 && !self.di {
+                    return;
+                }
+                self.shift = (self.shift << 1) | self.di as u16;
+                self.bits += 1;
+                if self.bits == EEPROM_COMMAND_BITS {
+                    self.execute_command();
+                }
+            }
+            EepromState::Read => {
+                self.do_ = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits == EEPROM_WORD_BITS {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Write(address) => {
+                self.shift = (self.shift << 1) | self.di as u16;
+                self.bits += 1;
+                if self.bits == EEPROM_WORD_BITS {
+                    match address {
+                        Some(address) => self.write_word(address, self.shift),
+                        None => {
+                            for address in 0..(EEPROM_SIZE / 2) as u8 {
+                                self.write_word(address, self.shift);
+                            }
+                        }
+                    }
+                    // Programming finishes instantly, so report ready
+                    self.do_ = true;
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+        }
+    }
+
+    fn execute_command(&mut self) {
+        let command = self.shift;
+        let op = (command >> 8) & 0b11;
+        let address = (command & 0x7F) as u8;
+        self.shift = 0;
+        self.bits = 0;
+
+        match op {
+            EEPROM_OP_READ => {
+                // A dummy 0 bit comes out before the data
+                self.do_ = false;
+                self.shift = self.read_word(address);
+                self.state = EepromState::Read;
+            }
+            EEPROM_OP_WRITE => self.state = EepromState::Write(Some(address)),
+            EEPROM_OP_ERASE => {
+                self.write_word(address, 0xFFFF);
+                self.do_ = true;
+            }
+            EEPROM_OP_EXTENDED => match (command >> 6) & 0b11 {
+                EEPROM_EXT_EWDS => self.write_enabled = false,
+                EEPROM_EXT_EWEN => self.write_enabled = true,
+                EEPROM_EXT_ERAL => {
+                    for address in 0..(EEPROM_SIZE / 2) as u8 {
+                        self.write_word(address, 0xFFFF);
+                    }
+                    self.do_ = true;
+                }
+                EEPROM_EXT_WRAL => self.state = EepromState::Write(None),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_enabled: [bool; 2],
+
+    tilt: (f32, f32),
+    accel_latched: bool,
+    accel_x: u16,
+    accel_y: u16,
+
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Mbc7 {
+        Mbc7 {
+            rom,
+            rom_bank_select: 1,
+            ram_enabled: [false, false],
+
+            tilt: (0.0, 0.0),
+            accel_latched: false,
+            accel_x: ACCEL_ERASED,
+            accel_y: ACCEL_ERASED,
+
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom.len() / RNG_ROM_BANK1.len()
+    }
+
+    fn regs_accessible(&self) -> bool {
+        self.ram_enabled[0] && self.ram_enabled[1]
+    }
+}
+
+fn tilt_to_accel(tilt: f32) -> u16 {
+    (ACCEL_CENTER + tilt * ACCEL_PER_G) as u16
+}
+
+impl MemDevice for Mbc7 {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_REGS) {
+            if !self.regs_accessible() {
+                return Ok(0xFF);
+            }
+            match (a.0 >> 4) & 0xF {
+                REG_ACCEL_X_LOW => Ok(self.accel_x as u8),
+                REG_ACCEL_X_HIGH => Ok((self.accel_x >> 8) as u8),
+                REG_ACCEL_Y_LOW => Ok(self.accel_y as u8),
+                REG_ACCEL_Y_HIGH => Ok((self.accel_y >> 8) as u8),
+                REG_ZERO => Ok(0x00),
+                REG_EEPROM => Ok(self.eeprom.read()),
+                _ => Ok(0xFF),
+            }
+        } else if a.in_(RNG_EXT_RAM) {
+            Ok(0xFF)
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        if a.in_(RNG_REGS) {
+            if !self.regs_accessible() {
+                error!("Error: MBC7 registers are not writable right now");
+                return Err(());
+            }
+            match (a.0 >> 4) & 0xF {
+                REG_LATCH_ERASE if v == LATCH_ERASE_VALUE => {
+                    self.accel_latched = false;
+                    self.accel_x = ACCEL_ERASED;
+                    self.accel_y = ACCEL_ERASED;
+                }
+                REG_LATCH if v == LATCH_VALUE && !self.accel_latched => {
+                    self.accel_latched = true;
+                    self.accel_x = tilt_to_accel(self.tilt.0);
+                    self.accel_y = tilt_to_accel(self.tilt.1);
+                }
+                REG_EEPROM => self.eeprom.write(v),
+                _ => {}
+            }
+            Ok(())
+        } else if a.in_(RNG_EXT_RAM) {
+            Ok(())
+        } else if a.in_(RNG_RAMG1) {
+            self.ram_enabled[0] = v & 0b1111 == 0x0A;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & 0b0111_1111) as usize;
+            Ok(())
+        } else if a.in_(RNG_RAMG2) {
+            self.ram_enabled[1] = v == 0x40;
+            Ok(())
+        } else if a.in_(RNG_UNUSED) {
+            Ok(())
+        } else {
+            error!("Unimplemented MBC7 register {}", a);
+            Err(())
+        }
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let bank = self.rom_bank_select % self.rom_bank_count();
+        ExtendedAddress((RNG_ROM_BANK1.len() * bank) as u32 + u32::from((a - RNG_ROM_BANK1.0).0))
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.eeprom.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        let len = buf.len().min(EEPROM_SIZE);
+        self.eeprom.data[..len].clone_from_slice(&buf[..len]);
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[cfg(test)]
+fn make_test_mbc() -> Mbc7 {
+    let mut mbc = Mbc7::new(vec![0; RNG_ROM_BANK1.len() * 4]);
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc.write(Address(0x4000), 0x40).unwrap();
+    mbc
+}
+
+#[cfg(test)]
+fn eeprom_command(op: u16, address: u8) -> u32 {
+    (1 << 10) | (u32::from(op) << 8) | u32::from(address)
+}
+
+#[cfg(test)]
+fn eeprom_transfer(mbc: &mut Mbc7, bits: u32, count: u8) -> u16 {
+    let mut out = 0;
+    for i in (0..count).rev() {
+        let di = if bits & (1 << i) != 0 { EEPROM_DI } else { 0 };
+        mbc.write(Address(0xA080), EEPROM_CS | di).unwrap();
+        mbc.write(Address(0xA080), EEPROM_CS | EEPROM_CLK | di)
+            .unwrap();
+        out = (out << 1) | u16::from(mbc.read(Address(0xA080)).unwrap() & EEPROM_DO);
+    }
+    mbc.write(Address(0xA080), 0).unwrap();
+    out
+}
+
+#[test]
+fn test_mbc7_accelerometer() {
+    let mut mbc = make_test_mbc();
+    mbc.set_tilt(1.0, -0.5);
+
+    mbc.write(Address(0xA000), LATCH_ERASE_VALUE).unwrap();
+    assert_eq!(mbc.read(Address(0xA030)), Ok(0x80));
+    mbc.write(Address(0xA010), LATCH_VALUE).unwrap();
+    assert_eq!(mbc.read(Address(0xA020)), Ok(0x40));
+    assert_eq!(mbc.read(Address(0xA030)), Ok(0x82));
+    assert_eq!(mbc.read(Address(0xA040)), Ok(0x98));
+    assert_eq!(mbc.read(Address(0xA050)), Ok(0x81));
+
+    // Latching again needs another erase first
+    mbc.set_tilt(0.0, 0.0);
+    mbc.write(Address(0xA010), LATCH_VALUE).unwrap();
+    assert_eq!(mbc.read(Address(0xA030)), Ok(0x82));
+
+    mbc.write(Address(0x4000), 0x00).unwrap();
+    assert_eq!(mbc.read(Address(0xA030)), Ok(0xFF));
+}
+
+#[test]
+fn test_mbc7_eeprom() {
+    let mut mbc = make_test_mbc();
+
+    // Writes are ignored until EWEN
+    eeprom_transfer(
+        &mut mbc,
+        (eeprom_command(EEPROM_OP_WRITE, 5) << 16) | 0x5678,
+        27,
+    );
+    assert_eq!(mbc.get_sram()[10], 0xFF);
+
+    eeprom_transfer(
+        &mut mbc,
+        eeprom_command(EEPROM_OP_EXTENDED, 0b1100_0000),
+        11,
+    );
+    eeprom_transfer(
+        &mut mbc,
+        (eeprom_command(EEPROM_OP_WRITE, 5) << 16) | 0x1234,
+        27,
+    );
+    assert_eq!(&mbc.get_sram()[10..12], &[0x34, 0x12]);
+
+    let v = eeprom_transfer(&mut mbc, eeprom_command(EEPROM_OP_READ, 5) << 16, 27);
+    assert_eq!(v, 0x1234);
+
+    let mut restored = make_test_mbc();
+    restored.set_sram(mbc.get_sram());
+    let v = eeprom_transfer(&mut restored, eeprom_command(EEPROM_OP_READ, 5) << 16, 27);
+    assert_eq!(v, 0x1234);
+}
@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cpu::CLOCK_RATE;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    // Follows the host's clock, including while the emulator is not running
+    WallTime,
+    // Follows emulated time only, which keeps runs deterministic
+    Emulated,
+}
+
+pub struct RtcTimebase {
+    clock: RtcClock,
+    last_wall_time: u64,
+    last_cycle: u64,
+    pending_cycles: u64,
+}
+
+impl RtcTimebase {
+    pub fn new() -> RtcTimebase {
+        RtcTimebase {
+            clock: RtcClock::WallTime,
+            last_wall_time: unix_time(),
+            last_cycle: 0,
+            pending_cycles: 0,
+        }
+    }
+
+    pub fn clock(&self) -> RtcClock {
+        self.clock
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.clock = clock;
+        self.last_wall_time = unix_time();
+        self.pending_cycles = 0;
+    }
+
+    pub fn pump_cycle(&mut self, cycle: u64) {
+        self.pending_cycles += cycle.saturating_sub(self.last_cycle);
+        self.last_cycle = cycle;
+    }
+
+    // Drops any partial second, as happens when the seconds register is
+    // written
+    pub fn reset_subsecond(&mut self) {
+        self.pending_cycles = 0;
+        self.last_wall_time = unix_time();
+    }
+
+    pub fn take_elapsed_seconds(&mut self) -> u64 {
+        match self.clock {
+            RtcClock::WallTime => {
+                let now = unix_time();
+                let elapsed = now.saturating_sub(self.last_wall_time);
+                self.last_wall_time = now;
+                elapsed
+            }
+            RtcClock::Emulated => {
+                let elapsed = self.pending_cycles / CLOCK_RATE;
+                self.pending_cycles %= CLOCK_RATE;
+                elapsed
+            }
+        }
+    }
+
+    // Time that passed while the emulator was not running. Only a wall time
+    // clock observes it.
+    pub fn offline_seconds(&self, saved_at: u64) -> u64 {
+        match self.clock {
+            RtcClock::WallTime => unix_time().saturating_sub(saved_at),
+            RtcClock::Emulated => 0,
+        }
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// The registers a cartridge's clock chip keeps, in whatever layout it uses
+pub trait RtcState: Default {
+    // Bytes the registers take up in the save footer
+    const SAVE_LEN: usize;
+
+    fn advance(&mut self, seconds: u64);
+    fn save(&self, buf: &mut [u8]);
+    fn load(&mut self, buf: &[u8]);
+}
+
+// A clock chip kept running by a timebase. It is saved after the cartridge
+// RAM as its registers followed by the unix time they were valid at as a
+// little endian u64, which lets a wall time clock catch up on load.
+pub struct Rtc<S: RtcState> {
+    pub state: S,
+    pub timebase: RtcTimebase,
+}
+
+impl<S: RtcState> Rtc<S> {
+    pub const SAVE_LEN: usize = S::SAVE_LEN + 8;
+
+    pub fn new() -> Rtc<S> {
+        Rtc {
+            state: S::default(),
+            timebase: RtcTimebase::new(),
+        }
+    }
+
+    // Returns true if any time went by
+    pub fn update(&mut self) -> bool {
+        let elapsed = self.timebase.take_elapsed_seconds();
+        if elapsed > 0 {
+            self.state.advance(elapsed);
+        }
+        elapsed > 0
+    }
+
+    // Returns true if any time went by
+    pub fn pump_cycle(&mut self, cycle: u64) -> bool {
+        self.timebase.pump_cycle(cycle);
+        self.timebase.clock() == RtcClock::Emulated && self.update()
+    }
+
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.update();
+        self.timebase.set_clock(clock);
+    }
+
+    pub fn save(&self, footer: &mut [u8]) {
+        self.state.save(&mut footer[..S::SAVE_LEN]);
+        footer[S::SAVE_LEN..Self::SAVE_LEN].copy_from_slice(&unix_time().to_le_bytes());
+    }
+
+    pub fn load(&mut self, footer: &[u8]) {
+        self.state.load(&footer[..S::SAVE_LEN]);
+
+        let mut saved_at = [0; 8];
+        saved_at.copy_from_slice(&footer[S::SAVE_LEN..Self::SAVE_LEN]);
+        let offline = self.timebase.offline_seconds(u64::from_le_bytes(saved_at));
+        self.state.advance(offline);
+        self.timebase.reset_subsecond();
+    }
+}
@@ -12,16 +12,22 @@ pub const RNG_LCD_BGDD2: AddressRange = AddressRange(Address(0x9C00), Address(0x
 pub const RNG_EXT_RAM: AddressRange = AddressRange(Address(0xA000), Address(0xC000));
 pub const RNG_INT_RAM_0: AddressRange = AddressRange(Address(0xC000), Address(0xD000));
 pub const RNG_INT_RAM_1: AddressRange = AddressRange(Address(0xD000), Address(0xE000));
+pub const RNG_ECHO_RAM: AddressRange = AddressRange(Address(0xE000), Address(0xFE00));
 pub const RNG_LCD_OAM: AddressRange = AddressRange(Address(0xFE00), Address(0xFEA0));
+pub const RNG_UNUSABLE: AddressRange = AddressRange(Address(0xFEA0), Address(0xFF00));
+pub const RNG_IO_REGS: AddressRange = AddressRange(Address(0xFF00), Address(0xFF80));
 pub const RNG_SND_REGS: AddressRange = AddressRange(Address(0xFF10), Address(0xFF27));
 pub const RNG_SND_WAV_RAM: AddressRange = AddressRange(Address(0xFF30), Address(0xFF40));
 pub const RNG_LCD_MM_REG: AddressRange = AddressRange(Address(0xFF40), Address(0xFF6C));
 pub const RNG_INT_TINY_RAM: AddressRange = AddressRange(Address(0xFF80), Address(0xFFFF));
+pub const RNG_CGB_BOOT_ROM_HIGH: AddressRange = AddressRange(Address(0x0200), Address(0x0900));
 
 pub const REG_INTR_ENABLE: Address = Address(0xFFFF);
 pub const REG_P1: Address = Address(0xFF00);
 pub const REG_DMA: Address = Address(0xFF46);
+pub const REG_KEY0: Address = Address(0xFF4C);
 pub const REG_KEY1: Address = Address(0xFF4D);
+pub const REG_BOOT: Address = Address(0xFF50);
 pub const REG_HDMA1: Address = Address(0xFF51);
 pub const REG_HDMA2: Address = Address(0xFF52);
 pub const REG_HDMA3: Address = Address(0xFF53);
@@ -139,18 +145,6 @@ impl AddressRange {
 pub trait MemDevice {
     fn read(&self, a: Address) -> Result<u8, ()>;
     fn write(&mut self, a: Address, v: u8) -> Result<(), ()>;
-
-    fn write16(&mut self, a: Address, v: u16) -> Result<(), ()> {
-        self.write(a, (v & 0xFF) as u8)?;
-        self.write(a + Address(1), ((v >> 8) & 0xFF) as u8)?;
-        Ok(())
-    }
-
-    fn read16(&self, a: Address) -> Result<u16, ()> {
-        let hi = u16::from(self.read(a + Address(1))?);
-        let lo = u16::from(self.read(a)?);
-        Ok(hi << 8 | lo)
-    }
 }
 
 #[derive(Clone, Debug)]
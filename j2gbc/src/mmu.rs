@@ -1,3 +1,4 @@
+use std::cmp::min;
 use std::collections::HashSet;
 
 use log::{error, info};
@@ -9,8 +10,44 @@ use crate::input::Input;
 use crate::lcd::Lcd;
 use crate::mem::*;
 use crate::mmu_exceptions::MmuExceptions;
+use crate::serial::Serial;
 use crate::timer::Timer;
 
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+// Written to KEY0 by the CGB boot ROM when the cartridge only supports DMG
+const KEY0_DMG_COMPATIBILITY: u8 = 0b0000_0100;
+
+const OAM_DMA_LEN: u16 = 0xA0;
+
+const IF_UNUSED_BITS: u8 = 0b1110_0000;
+const KEY1_UNUSED_BITS: u8 = 0b0111_1110;
+const SVBK_UNUSED_BITS: u8 = 0b1111_1000;
+
+const ECHO_OFFSET: Address = Address(0x2000);
+
+const HDMA_HBLANK_MODE: u8 = 0b1000_0000;
+const HDMA_BLOCK_LEN: u16 = 0x10;
+// 8us per block, half that in double speed
+const HDMA_BLOCK_CYCLES: u64 = 32;
+
+#[derive(Clone, Copy)]
+struct OamDma {
+    src: Address,
+    // The cycle the first byte is copied on
+    start: u64,
+    copied: u16,
+    // Whatever the transfer last put on the bus
+    last: u8,
+}
+
+#[derive(PartialEq)]
+enum Bus {
+    External,
+    Video,
+}
+
 pub struct Mmu {
     internal_ram: Ram,
     tiny_ram: Ram,
@@ -26,22 +63,44 @@ pub struct Mmu {
     pub lcd: Box<Lcd>,
     pub audio: Audio,
     pub timer: Timer,
+    pub serial: Serial,
     pub input: Input,
     pub pedantic: bool,
+    cgb_mode: bool,
+    // Stays set for a CGB running a DMG cart in compatibility mode
+    cgb_hardware: bool,
 
     pub watchpoints: HashSet<Address>,
 
     exceptions: MmuExceptions,
 
+    boot_rom: Option<Vec<u8>>,
+    key0: u8,
+
+    dma: u8,
+    oam_dma: Option<OamDma>,
+    oam_dma_restart: Option<OamDma>,
+    oam_dma_requested: bool,
+
     hdma1: u8,
     hdma2: u8,
     hdma3: u8,
     hdma4: u8,
     hdma5: u8,
+    hdma_hblank_active: bool,
+    hdma_src: Address,
+    hdma_dest: Address,
+
+    stall_cycles: u64,
 }
 
 impl Mmu {
-    pub fn new(cart: Cart, audio_sink: Box<dyn AudioSink + Send>, cgb_mode: bool) -> Mmu {
+    pub fn new(
+        cart: Cart,
+        audio_sink: Box<dyn AudioSink + Send>,
+        cgb_mode: bool,
+        boot_rom: Option<Vec<u8>>,
+    ) -> Mmu {
         Mmu {
             internal_ram: Ram::new(RNG_INT_RAM_0.len() * 8),
             tiny_ram: Ram::new(RNG_INT_TINY_RAM.len()),
@@ -52,35 +111,104 @@ impl Mmu {
             exceptions: cart.get_mmu_exceptions(),
             cart,
             lcd: Box::new(Lcd::new(cgb_mode)),
-            audio: Audio::new(audio_sink),
+            audio: Audio::new(audio_sink, cgb_mode),
             timer: Timer::new(),
+            serial: Serial::new(cgb_mode),
             input: Input::new(),
             pedantic: true,
+            cgb_mode,
+            cgb_hardware: cgb_mode,
             ram_bank_select: 1,
 
+            boot_rom,
+            key0: 0,
+
+            dma: 0,
+            oam_dma: None,
+            oam_dma_restart: None,
+            oam_dma_requested: false,
+
             hdma1: 0,
             hdma2: 0,
             hdma3: 0,
             hdma4: 0,
             hdma5: 0,
+            hdma_hblank_active: false,
+            hdma_src: Address(0),
+            hdma_dest: Address(0),
+
+            stall_cycles: 0,
 
             watchpoints: HashSet::new(),
         }
     }
 
-    fn dma(&mut self, mut src: Address) -> Result<(), ()> {
-        // TODO: This should actually take 160us worth of cycles
-        let mut dst = RNG_LCD_OAM.0;
-        while dst < RNG_LCD_OAM.1 {
-            let v = self.read(src)?;
-            self.write(dst, v)?;
-            dst += Address(1);
-            src += Address(1);
+    // Moves OAM DMA along to `cycle`, copying one byte per M-cycle
+    pub fn pump_oam_dma(&mut self, cycle: u64) -> Result<(), ()> {
+        let m_cycle = if self.double_speed_mode { 2 } else { 4 };
+
+        if self.oam_dma_requested {
+            self.oam_dma_requested = false;
+            // A transfer starts after an M-cycle of setup, and one that is
+            // already running keeps going until then
+            self.oam_dma_restart = Some(OamDma {
+                src: oam_dma_source(self.dma),
+                start: cycle + m_cycle,
+                copied: 0,
+                last: 0xFF,
+            });
+        }
+
+        if let Some(restart) = self.oam_dma_restart {
+            if cycle >= restart.start {
+                self.run_oam_dma(restart.start - 1, m_cycle)?;
+                self.oam_dma = Some(restart);
+                self.oam_dma_restart = None;
+            }
+        }
+
+        self.run_oam_dma(cycle, m_cycle)
+    }
+
+    fn run_oam_dma(&mut self, until: u64, m_cycle: u64) -> Result<(), ()> {
+        let mut dma = match self.oam_dma {
+            Some(dma) => dma,
+            None => return Ok(()),
+        };
+
+        if until >= dma.start {
+            let due = min(u64::from(OAM_DMA_LEN), (until - dma.start) / m_cycle + 1) as u16;
+            while dma.copied < due {
+                let offset = Address(dma.copied);
+                let v = self.bus_read(dma.src + offset)?;
+                self.lcd.write(RNG_LCD_OAM.0 + offset, v)?;
+                dma.last = v;
+                dma.copied += 1;
+            }
         }
 
+        self.oam_dma = if dma.copied < OAM_DMA_LEN {
+            Some(dma)
+        } else {
+            None
+        };
         Ok(())
     }
 
+    // While OAM DMA runs, OAM belongs to it and anything else on the bus it
+    // is reading from sees the byte being transferred. HRAM, I/O and the
+    // other bus are unaffected.
+    fn oam_dma_conflict(&self, a: Address) -> Option<u8> {
+        let dma = self.oam_dma.as_ref()?;
+        if a.in_(RNG_LCD_OAM) {
+            Some(0xFF)
+        } else if bus(a).is_some() && bus(a) == bus(dma.src) {
+            Some(dma.last)
+        } else {
+            None
+        }
+    }
+
     fn hdma(&mut self, src: AddressRange, mut dest: Address) -> Result<(), ()> {
         let mut src_cursor = src.0;
         while src_cursor < src.1 {
@@ -92,22 +220,95 @@ impl Mmu {
         Ok(())
     }
 
+    // Copies the next block of an HBlank HDMA, if one is running. HDMA5 counts
+    // down the remaining blocks and reads 0xFF once the transfer is done.
+    pub fn hblank_hdma(&mut self) -> Result<(), ()> {
+        if !self.hdma_hblank_active {
+            return Ok(());
+        }
+
+        let src = self.hdma_src;
+        let src_end = Address(src.0.wrapping_add(HDMA_BLOCK_LEN));
+        self.hdma(AddressRange(src, src_end), self.hdma_dest)?;
+        self.hdma_src = src_end;
+        self.hdma_dest = hdma_dest_address(self.hdma_dest.0.wrapping_add(HDMA_BLOCK_LEN));
+        self.stall_cycles += self.hdma_block_cycles();
+
+        if self.hdma5 == 0 {
+            self.hdma_hblank_active = false;
+            self.hdma5 = 0xFF;
+        } else {
+            self.hdma5 -= 1;
+        }
+        Ok(())
+    }
+
+    fn hdma_block_cycles(&self) -> u64 {
+        if self.double_speed_mode {
+            HDMA_BLOCK_CYCLES / 2
+        } else {
+            HDMA_BLOCK_CYCLES
+        }
+    }
+
+    // Cycles the CPU has lost to DMA since the last call
+    pub fn take_stall_cycles(&mut self) -> u64 {
+        let cycles = self.stall_cycles;
+        self.stall_cycles = 0;
+        cycles
+    }
+
+    fn read_boot_rom(&self, a: Address) -> Option<u8> {
+        let rom = self.boot_rom.as_ref()?;
+        if a.in_(RNG_INTR_TABLE) || (rom.len() == CGB_BOOT_ROM_SIZE && a.in_(RNG_CGB_BOOT_ROM_HIGH))
+        {
+            Some(rom[a.0 as usize])
+        } else {
+            None
+        }
+    }
+
+    fn unmap_boot_rom(&mut self) {
+        if self.boot_rom.take().is_some() && self.key0 & KEY0_DMG_COMPATIBILITY != 0 {
+            self.cgb_mode = false;
+            self.lcd.set_dmg_compatibility();
+            self.serial.set_dmg_compatibility();
+        }
+    }
+
+    // What's left between OAM and the I/O registers reads back differently on
+    // each model
+    fn read_unusable(&self, a: Address) -> u8 {
+        if self.cgb_hardware {
+            let nibble = a.0 as u8 & 0xF0;
+            nibble | nibble >> 4
+        } else if self.lcd.is_oam_blocked() {
+            0xFF
+        } else {
+            0
+        }
+    }
+
     fn _read(&self, a: Address) -> Result<u8, ()> {
         if self.watchpoints.contains(&a) {
             info!("Read watchpoint for {:?}", a);
             Err(())
+        } else if let Some(v) = self.read_boot_rom(a) {
+            Ok(v)
+        } else if a == REG_BOOT || (!self.cgb_mode && is_cgb_register(a)) {
+            Ok(0xFF)
+        } else if a == REG_KEY0 {
+            Ok(self.key0)
+        } else if a == REG_DMA {
+            Ok(self.dma)
         } else if a == REG_SVBK {
-            Ok(self.ram_bank_select as u8)
-        } else if a == REG_HDMA1 {
-            Ok(self.hdma1)
-        } else if a == REG_HDMA2 {
-            Ok(self.hdma2)
-        } else if a == REG_HDMA3 {
-            Ok(self.hdma3)
-        } else if a == REG_HDMA4 {
-            Ok(self.hdma4)
+            Ok(self.ram_bank_select as u8 | SVBK_UNUSED_BITS)
         } else if a == REG_HDMA5 {
             Ok(self.hdma5)
+        } else if a.in_(RNG_ECHO_RAM) {
+            self._read(a - ECHO_OFFSET)
+        } else if a.in_(RNG_UNUSABLE) {
+            Ok(self.read_unusable(a))
         } else if a.in_(RNG_INT_RAM_0) {
             self.internal_ram.read(a - RNG_INT_RAM_0.0)
         } else if a.in_(RNG_INT_RAM_1) {
@@ -120,7 +321,7 @@ impl Mmu {
                 0
             };
             let prepared_mode = if self.prepared_speed_switch { 1 } else { 0 };
-            Ok(mode | prepared_mode)
+            Ok(mode | prepared_mode | KEY1_UNUSED_BITS)
         } else if a.in_(RNG_ROM_BANK0)
             || a.in_(RNG_ROM_BANK1)
             || a.in_(RNG_EXT_RAM)
@@ -141,10 +342,12 @@ impl Mmu {
         } else {
             match a {
                 REG_INTR_ENABLE => Ok(self.interrupt_enable),
-                REG_INTR_FLAG => Ok(self.interrupt_flag),
+                REG_INTR_FLAG => Ok(self.interrupt_flag | IF_UNUSED_BITS),
                 REG_TIMA | REG_DIV | REG_TAC | REG_TMA => self.timer.read(a),
                 REG_P1 => self.input.read(a),
-                REG_SB | REG_SC => Ok(0),
+                REG_SB | REG_SC => self.serial.read(a),
+                // Open bus, including the write-only HDMA registers
+                _ if a.in_(RNG_IO_REGS) => Ok(0xFF),
                 _ => {
                     error!("MMU: Unimplemented memory read at address {:?}", a);
                     Err(())
@@ -157,11 +360,25 @@ impl Mmu {
         if self.watchpoints.contains(&a) {
             info!("Write watchpoint for {:?}", a);
             Err(())
-        } else if a == REG_RP {
-            // IR not supported right now
+        } else if a == REG_RP || (!self.cgb_mode && is_cgb_register(a)) {
+            // IR not supported right now, and a DMG has none of the CGB
+            // registers
+            Ok(())
+        } else if a == REG_BOOT {
+            if v & 0b1 != 0 {
+                self.unmap_boot_rom();
+            }
+            Ok(())
+        } else if a == REG_KEY0 {
+            // Locked once the boot ROM is gone
+            if self.boot_rom.is_some() {
+                self.key0 = v;
+            }
             Ok(())
         } else if a == REG_DMA {
-            self.dma(Address((u16::from(v)) << 8))
+            self.dma = v;
+            self.oam_dma_requested = true;
+            Ok(())
         } else if a == REG_HDMA1 {
             self.hdma1 = v;
             Ok(())
@@ -175,14 +392,31 @@ impl Mmu {
             self.hdma4 = v;
             Ok(())
         } else if a == REG_HDMA5 {
-            if v & 0b1000_0000 != 0 {
-                panic!("HDMA HBlank not supported");
+            if self.hdma_hblank_active && v & HDMA_HBLANK_MODE == 0 {
+                // Cancelled, leaving the remaining length readable
+                self.hdma_hblank_active = false;
+                self.hdma5 |= HDMA_HBLANK_MODE;
+                return Ok(());
             }
+
             let src_start = Address(hi_lo(self.hdma1, self.hdma2) & 0b1111_1111_1111_0000);
-            let src_end = src_start + Address((u16::from(v & 0b0111_1111) + 1) * 16);
-            let dest =
-                Address((hi_lo(self.hdma3, self.hdma4) & 0b0001_1111_1111_0000) | (0b1 << 15));
+            let dest = hdma_dest_address(hi_lo(self.hdma3, self.hdma4));
+            if v & HDMA_HBLANK_MODE != 0 {
+                self.hdma_src = src_start;
+                self.hdma_dest = dest;
+                self.hdma5 = v & 0b0111_1111;
+                self.hdma_hblank_active = true;
+                // Starting mid-HBlank doesn't wait for the next one
+                if self.lcd.is_in_hblank() {
+                    self.hblank_hdma()?;
+                }
+                return Ok(());
+            }
+
+            let blocks = u16::from(v & 0b0111_1111) + 1;
+            let src_end = src_start + Address(blocks * HDMA_BLOCK_LEN);
             self.hdma5 = 0xFF;
+            self.stall_cycles += u64::from(blocks) * self.hdma_block_cycles();
             self.hdma(AddressRange(src_start, src_end), dest)
         } else if a == REG_KEY1 {
             self.prepared_speed_switch = (0b1 & v) == 1;
@@ -190,6 +424,10 @@ impl Mmu {
         } else if a == REG_SVBK {
             self.ram_bank_select = usize::from(v & 0b111);
             Ok(())
+        } else if a.in_(RNG_ECHO_RAM) {
+            self._write(a - ECHO_OFFSET, v)
+        } else if a.in_(RNG_UNUSABLE) {
+            Ok(())
         } else if a.in_(RNG_INT_RAM_0) {
             self.internal_ram.write(a - RNG_INT_RAM_0.0, v)
         } else if a.in_(RNG_INT_RAM_1) {
@@ -224,7 +462,8 @@ impl Mmu {
                 }
                 REG_TIMA | REG_DIV | REG_TAC | REG_TMA => self.timer.write(a, v),
                 REG_P1 => self.input.write(a, v),
-                REG_SB | REG_SC => Ok(()),
+                REG_SB | REG_SC => self.serial.write(a, v),
+                _ if a.in_(RNG_IO_REGS) => Ok(()),
                 _ => {
                     error!("MMU: Unimplemented memory write at address {:?}", a);
                     Err(())
@@ -235,21 +474,32 @@ impl Mmu {
 
     pub fn toggle_double_speed(&mut self) {
         self.double_speed_mode = !self.double_speed_mode;
+        self.prepared_speed_switch = false;
         self.timer.toggle_double_speed();
+        self.serial.toggle_double_speed();
     }
-}
 
-impl MemDevice for Mmu {
-    fn read(&self, a: Address) -> Result<u8, ()> {
+    fn bus_read(&self, a: Address) -> Result<u8, ()> {
         if self.pedantic && !self.exceptions.allow(a) {
             self._read(a)
         } else {
             self._read(a).or(Ok(0))
         }
     }
+}
+
+impl MemDevice for Mmu {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        match self.oam_dma_conflict(a) {
+            Some(v) => Ok(v),
+            None => self.bus_read(a),
+        }
+    }
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
-        if self.pedantic && !self.exceptions.allow(a) {
+        if self.oam_dma_conflict(a).is_some() {
+            Ok(())
+        } else if self.pedantic && !self.exceptions.allow(a) {
             self._write(a, v)
         } else {
             self._write(a, v).or(Ok(()))
@@ -257,8 +507,188 @@ impl MemDevice for Mmu {
     }
 }
 
+fn oam_dma_source(v: u8) -> Address {
+    // Sources past work RAM read its echo
+    let page = if v >= 0xE0 { v - 0x20 } else { v };
+    Address(u16::from(page) << 8)
+}
+
+fn is_cgb_register(a: Address) -> bool {
+    matches!(
+        a.0,
+        0xFF4C..=0xFF4D | 0xFF4F | 0xFF51..=0xFF56 | 0xFF68..=0xFF6B | 0xFF70
+    )
+}
+
+fn bus(a: Address) -> Option<Bus> {
+    if a.in_(RNG_CHAR_DAT) || a.in_(RNG_LCD_BGDD1) || a.in_(RNG_LCD_BGDD2) {
+        Some(Bus::Video)
+    } else if a < RNG_LCD_OAM.0 {
+        Some(Bus::External)
+    } else {
+        None
+    }
+}
+
+fn hdma_dest_address(v: u16) -> Address {
+    Address((v & 0b0001_1111_1111_0000) | (0b1 << 15))
+}
+
 fn ram_bank_adjust(a: Address, bank: usize) -> Address {
     let bank_offset =
         RNG_INT_RAM_1.len() * if bank > 0 { bank - 1 } else { 0 } + RNG_INT_RAM_0.len();
     (a - RNG_INT_RAM_1.0) + Address(bank_offset as u16)
 }
+
+#[cfg(test)]
+fn make_hdma_test_system(hdma5_writes: &[u8]) -> crate::system::System {
+    // ld a, $02; ldh [HDMA1], a; xor a; ldh [HDMA2], a; ldh [HDMA3], a;
+    // ldh [HDMA4], a; then ld a, n; ldh [HDMA5], a for each write; jr @
+    let mut program = vec![
+        0x3E, 0x02, 0xE0, 0x51, 0xAF, 0xE0, 0x52, 0xE0, 0x53, 0xE0, 0x54,
+    ];
+    for v in hdma5_writes {
+        program.extend_from_slice(&[0x3E, *v, 0xE0, 0x55]);
+    }
+    program.extend_from_slice(&[0x18, 0xFE]);
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    rom[0x143] = 0x80;
+    for (i, v) in rom[0x200..0x280].iter_mut().enumerate() {
+        *v = i as u8 + 1;
+    }
+    crate::system::System::new(
+        std::io::Cursor::new(rom),
+        Box::new(crate::NullSink),
+        true,
+        None,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_hblank_hdma() {
+    let mut system = make_hdma_test_system(&[0x81]);
+    system.run_for_duration(&std::time::Duration::from_millis(1));
+
+    let debugger = system.debugger();
+    for i in 0..0x20 {
+        assert_eq!(debugger.read_mem(Address(0x8000 + i)), Ok(i as u8 + 1));
+    }
+    assert_eq!(debugger.read_mem(Address(0x8020)), Ok(0));
+    assert_eq!(debugger.read_mem(REG_HDMA5), Ok(0xFF));
+}
+
+#[test]
+fn test_hblank_hdma_cancel() {
+    let mut system = make_hdma_test_system(&[0x87, 0x00]);
+    system.run_for_duration(&std::time::Duration::from_millis(1));
+
+    let debugger = system.debugger();
+    let hdma5 = debugger.read_mem(REG_HDMA5).unwrap();
+    assert_eq!(hdma5 & 0b1000_0000, 0b1000_0000);
+    assert!(hdma5 & 0b0111_1111 >= 6);
+    assert_eq!(debugger.read_mem(Address(0x8070)), Ok(0));
+}
+
+#[cfg(test)]
+fn make_oam_dma_test_mmu() -> Mmu {
+    let mut rom = vec![0; 0x8000];
+    for i in 0..usize::from(OAM_DMA_LEN) {
+        rom[0x200 + i] = i as u8 + 1;
+        rom[0x300 + i] = 0x80 | i as u8;
+    }
+    let cart = Cart::load(std::io::Cursor::new(rom)).unwrap();
+    Mmu::new(cart, Box::new(crate::NullSink), false, None)
+}
+
+#[test]
+fn test_oam_dma() {
+    let mut mmu = make_oam_dma_test_mmu();
+    mmu.write(REG_DMA, 0x02).unwrap();
+    mmu.pump_oam_dma(0).unwrap();
+    // Setup takes an M-cycle, then ten bytes
+    mmu.pump_oam_dma(4 + 4 * 9).unwrap();
+
+    assert_eq!(mmu.lcd.read(Address(0xFE09)), Ok(10));
+    assert_eq!(mmu.lcd.read(Address(0xFE0A)), Ok(0));
+    assert_eq!(mmu.read(Address(0x0000)), Ok(10));
+    assert_eq!(mmu.read(Address(0xC000)), Ok(10));
+    assert_eq!(mmu.read(Address(0xFE00)), Ok(0xFF));
+    assert_eq!(mmu.read(Address(0x8000)), Ok(0));
+    mmu.write(Address(0xFF80), 0x12).unwrap();
+    assert_eq!(mmu.read(Address(0xFF80)), Ok(0x12));
+
+    mmu.pump_oam_dma(4 + 4 * 160).unwrap();
+    assert_eq!(mmu.read(Address(0xFE9F)), Ok(160));
+    assert_eq!(mmu.read(Address(0x0200)), Ok(1));
+}
+
+#[test]
+fn test_oam_dma_restart() {
+    let mut mmu = make_oam_dma_test_mmu();
+    mmu.write(REG_DMA, 0x02).unwrap();
+    mmu.pump_oam_dma(0).unwrap();
+    mmu.pump_oam_dma(4 + 4 * 19).unwrap();
+
+    // The first transfer keeps going through the second's setup
+    mmu.write(REG_DMA, 0x03).unwrap();
+    mmu.pump_oam_dma(4 + 4 * 19).unwrap();
+    assert_eq!(mmu.lcd.read(Address(0xFE14)), Ok(0));
+    mmu.pump_oam_dma(4 + 4 * 20).unwrap();
+    assert_eq!(mmu.lcd.read(Address(0xFE13)), Ok(20));
+    assert_eq!(mmu.lcd.read(Address(0xFE14)), Ok(0));
+    assert_eq!(mmu.lcd.read(Address(0xFE00)), Ok(0x80));
+
+    mmu.pump_oam_dma(4 + 4 * 20 + 4 * 160).unwrap();
+    assert_eq!(mmu.read(Address(0xFE9F)), Ok(0x80 | 159));
+}
+
+#[cfg(test)]
+fn make_test_mmu(cgb_mode: bool) -> Mmu {
+    let mut rom = vec![0; 0x8000];
+    if cgb_mode {
+        rom[0x143] = 0x80;
+    }
+    let cart = Cart::load(std::io::Cursor::new(rom)).unwrap();
+    Mmu::new(cart, Box::new(crate::NullSink), cgb_mode, None)
+}
+
+#[test]
+fn test_echo_ram() {
+    let mut mmu = make_test_mmu(false);
+    mmu.write(Address(0xC123), 0x42).unwrap();
+    assert_eq!(mmu.read(Address(0xE123)), Ok(0x42));
+    mmu.write(Address(0xFDFF), 0x24).unwrap();
+    assert_eq!(mmu.read(Address(0xDDFF)), Ok(0x24));
+}
+
+#[test]
+fn test_unusable_region() {
+    // The PPU starts out scanning OAM
+    let mut mmu = make_test_mmu(false);
+    mmu.write(Address(0xFEA0), 0x42).unwrap();
+    assert_eq!(mmu.read(Address(0xFEA0)), Ok(0xFF));
+    mmu.write(Address(0xFF40), 0).unwrap();
+    assert_eq!(mmu.read(Address(0xFEA0)), Ok(0));
+
+    let mmu = make_test_mmu(true);
+    assert_eq!(mmu.read(Address(0xFEA0)), Ok(0xAA));
+    assert_eq!(mmu.read(Address(0xFEF7)), Ok(0xFF));
+}
+
+#[test]
+fn test_open_bus_io() {
+    let mut mmu = make_test_mmu(false);
+    assert_eq!(mmu.read(Address(0xFF03)), Ok(0xFF));
+    assert_eq!(mmu.read(Address(0xFF7F)), Ok(0xFF));
+    assert_eq!(mmu.read(REG_SVBK), Ok(0xFF));
+    assert_eq!(mmu.read(REG_INTR_FLAG), Ok(0xE0));
+    assert_eq!(mmu.read(REG_TAC), Ok(0xF8));
+    mmu.write(Address(0xFF03), 0x12).unwrap();
+
+    let mmu = make_test_mmu(true);
+    assert_eq!(mmu.read(REG_SVBK), Ok(0xF9));
+    assert_eq!(mmu.read(REG_HDMA1), Ok(0xFF));
+}
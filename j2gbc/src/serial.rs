@@ -0,0 +1,242 @@
+use super::cpu::{Interrupt, InterruptSet, CLOCK_RATE};
+use super::mem::*;
+
+mod socket;
+
+pub use self::socket::SocketLink;
+
+const SC_TRANSFER_FLAG: u8 = 0b1000_0000;
+const SC_FAST_CLOCK_FLAG: u8 = 0b0000_0010;
+const SC_INTERNAL_CLOCK_FLAG: u8 = 0b0000_0001;
+
+const SC_UNUSED_BITS_DMG: u8 = 0b0111_1110;
+const SC_UNUSED_BITS_CGB: u8 = 0b0111_1100;
+
+const BITS_PER_TRANSFER: u64 = 8;
+const NORMAL_BIT_CYCLE_COUNT: u64 = CLOCK_RATE / 8_192;
+const FAST_BIT_CYCLE_COUNT: u64 = CLOCK_RATE / 262_144;
+
+pub trait SerialDevice {
+    fn exchange(&mut self, out: u8) -> u8;
+
+    // Called with the current cycle every time the serial port is pumped,
+    // before any transfer is completed.
+    fn advance(&mut self, _cycle: u64) {}
+
+    // Polled while a transfer is waiting on the external clock. Returning a
+    // value means the device clocked in a full byte and received `out`.
+    fn poll_external(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+}
+
+pub struct NullSerialDevice;
+
+impl SerialDevice for NullSerialDevice {
+    fn exchange(&mut self, _: u8) -> u8 {
+        // Nothing is connected, so the input line floats high
+        0xFF
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+
+    cgb_mode: bool,
+    double_speed: bool,
+
+    last_cycle: u64,
+    transfer_done_cycle: Option<u64>,
+
+    device: Box<dyn SerialDevice + Send>,
+}
+
+impl Serial {
+    pub fn new(cgb_mode: bool) -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+
+            cgb_mode,
+            double_speed: false,
+
+            last_cycle: 0,
+            transfer_done_cycle: None,
+
+            device: Box::new(NullSerialDevice),
+        }
+    }
+
+    pub fn set_dmg_compatibility(&mut self) {
+        self.cgb_mode = false;
+    }
+
+    pub fn attach_device(&mut self, device: Box<dyn SerialDevice + Send>) {
+        self.device = device;
+    }
+
+    pub fn detach_device(&mut self) -> Box<dyn SerialDevice + Send> {
+        std::mem::replace(&mut self.device, Box::new(NullSerialDevice))
+    }
+
+    pub fn toggle_double_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+    }
+
+    fn transfer_requested(&self) -> bool {
+        self.sc & SC_TRANSFER_FLAG != 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.sc & SC_INTERNAL_CLOCK_FLAG != 0
+    }
+
+    fn transfer_duration(&self) -> u64 {
+        let bit_cycle_count = if self.cgb_mode && self.sc & SC_FAST_CLOCK_FLAG != 0 {
+            FAST_BIT_CYCLE_COUNT
+        } else {
+            NORMAL_BIT_CYCLE_COUNT
+        };
+
+        if self.double_speed {
+            bit_cycle_count * BITS_PER_TRANSFER / 2
+        } else {
+            bit_cycle_count * BITS_PER_TRANSFER
+        }
+    }
+
+    pub fn get_next_event_cycle(&self) -> u64 {
+        self.transfer_done_cycle.unwrap_or(u64::MAX)
+    }
+
+    pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
+        self.last_cycle = cycle;
+        self.device.advance(cycle);
+
+        if !self.transfer_requested() {
+            return InterruptSet::default();
+        }
+
+        if self.internal_clock() {
+            match self.transfer_done_cycle {
+                Some(done) if done <= cycle => {
+                    self.sb = self.device.exchange(self.sb);
+                    self.finish_transfer()
+                }
+                _ => InterruptSet::default(),
+            }
+        } else if let Some(v) = self.device.poll_external(self.sb) {
+            self.sb = v;
+            self.finish_transfer()
+        } else {
+            InterruptSet::default()
+        }
+    }
+
+    fn finish_transfer(&mut self) -> InterruptSet {
+        self.sc &= !SC_TRANSFER_FLAG;
+        self.transfer_done_cycle = None;
+        Interrupt::Serial.into()
+    }
+}
+
+impl MemDevice for Serial {
+    fn read(&self, a: Address) -> Result<u8, ()> {
+        match a {
+            REG_SB => Ok(self.sb),
+            REG_SC => {
+                let unused = if self.cgb_mode {
+                    SC_UNUSED_BITS_CGB
+                } else {
+                    SC_UNUSED_BITS_DMG
+                };
+                Ok(self.sc | unused)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        match a {
+            REG_SB => {
+                self.sb = v;
+            }
+            REG_SC => {
+                self.sc = v & (SC_TRANSFER_FLAG | SC_FAST_CLOCK_FLAG | SC_INTERNAL_CLOCK_FLAG);
+                self.transfer_done_cycle = if self.transfer_requested() && self.internal_clock() {
+                    Some(self.last_cycle + self.transfer_duration())
+                } else {
+                    None
+                };
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct EchoDevice;
+
+#[cfg(test)]
+impl SerialDevice for EchoDevice {
+    fn exchange(&mut self, out: u8) -> u8 {
+        !out
+    }
+
+    fn poll_external(&mut self, out: u8) -> Option<u8> {
+        Some(!out)
+    }
+}
+
+#[test]
+fn test_internal_clock_transfer() {
+    let mut serial = Serial::new(false);
+    serial.attach_device(Box::new(EchoDevice));
+    serial.pump_cycle(100);
+
+    serial.write(REG_SB, 0x0F).unwrap();
+    serial.write(REG_SC, 0x81).unwrap();
+    assert_eq!(serial.get_next_event_cycle(), 100 + 4096);
+
+    assert_eq!(serial.pump_cycle(100 + 4095).if_(), 0);
+    assert_eq!(serial.read(REG_SB).unwrap(), 0x0F);
+    assert_eq!(serial.read(REG_SC).unwrap(), 0xFF);
+
+    assert_eq!(
+        serial.pump_cycle(100 + 4096).if_(),
+        Interrupt::Serial.bits()
+    );
+    assert_eq!(serial.read(REG_SB).unwrap(), 0xF0);
+    assert_eq!(serial.read(REG_SC).unwrap(), 0x7F);
+}
+
+#[test]
+fn test_fast_clock() {
+    let mut serial = Serial::new(false);
+    serial.write(REG_SC, 0x83).unwrap();
+    assert_eq!(serial.get_next_event_cycle(), 4096);
+
+    let mut serial = Serial::new(true);
+    serial.write(REG_SC, 0x83).unwrap();
+    assert_eq!(serial.get_next_event_cycle(), 128);
+
+    serial.toggle_double_speed();
+    serial.write(REG_SC, 0x83).unwrap();
+    assert_eq!(serial.get_next_event_cycle(), 64);
+}
+
+#[test]
+fn test_external_clock() {
+    let mut serial = Serial::new(false);
+    serial.write(REG_SB, 0x42).unwrap();
+    serial.write(REG_SC, 0x80).unwrap();
+    assert_eq!(serial.pump_cycle(10_000).if_(), 0);
+    assert_eq!(serial.read(REG_SB).unwrap(), 0x42);
+
+    serial.attach_device(Box::new(EchoDevice));
+    assert_eq!(serial.pump_cycle(10_001).if_(), Interrupt::Serial.bits());
+    assert_eq!(serial.read(REG_SB).unwrap(), 0xBD);
+}
@@ -0,0 +1,296 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use log::{error, info};
+
+use super::SerialDevice;
+
+// How far (in cycles) one console may run ahead of the last cycle it heard
+// from its peer before it waits for the peer to catch up. This is what keeps
+// the two cycle counters from drifting apart while tolerating latency.
+const MAX_LEAD_CYCLES: u64 = 70_224;
+const SYNC_INTERVAL_CYCLES: u64 = 4_096;
+const RECEIVE_INTERVAL_CYCLES: u64 = 256;
+
+const MSG_SYNC: u8 = 0;
+const MSG_TRANSFER: u8 = 1;
+const MSG_REPLY: u8 = 2;
+const MSG_LEN: usize = 10;
+
+trait LinkStream: Read + Write + Send {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl LinkStream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl LinkStream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_sockets_unsupported() -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        "Unix domain sockets aren't supported on this platform",
+    )
+}
+
+pub struct SocketLink {
+    stream: Option<Box<dyn LinkStream>>,
+    recv_buf: Vec<u8>,
+
+    cycle: u64,
+    peer_cycle: u64,
+    last_sync_cycle: u64,
+    last_receive_cycle: u64,
+
+    waiting_out: Option<u8>,
+    pending_transfer: Option<(u8, u64)>,
+    delivered: Option<u8>,
+    reply: Option<u8>,
+}
+
+impl SocketLink {
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<SocketLink> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, peer) = listener.accept()?;
+        info!("Link cable connected to {}", peer);
+        stream.set_nodelay(true)?;
+        Ok(SocketLink::new(Box::new(stream)))
+    }
+
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<SocketLink> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(SocketLink::new(Box::new(stream)))
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> io::Result<SocketLink> {
+        let listener = UnixListener::bind(path)?;
+        let (stream, _) = listener.accept()?;
+        info!("Link cable connected");
+        Ok(SocketLink::new(Box::new(stream)))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<SocketLink> {
+        Ok(SocketLink::new(Box::new(UnixStream::connect(path)?)))
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen_unix<P: AsRef<Path>>(_path: P) -> io::Result<SocketLink> {
+        Err(unix_sockets_unsupported())
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect_unix<P: AsRef<Path>>(_path: P) -> io::Result<SocketLink> {
+        Err(unix_sockets_unsupported())
+    }
+
+    fn new(stream: Box<dyn LinkStream>) -> SocketLink {
+        SocketLink {
+            stream: Some(stream),
+            recv_buf: Vec::new(),
+
+            cycle: 0,
+            peer_cycle: 0,
+            last_sync_cycle: 0,
+            last_receive_cycle: 0,
+
+            waiting_out: None,
+            pending_transfer: None,
+            delivered: None,
+            reply: None,
+        }
+    }
+
+    fn disconnect(&mut self, e: &io::Error) {
+        error!("Link cable disconnected: {}", e);
+        self.stream = None;
+        self.pending_transfer = None;
+    }
+
+    fn send(&mut self, tag: u8, v: u8, cycle: u64) {
+        let mut msg = [0; MSG_LEN];
+        msg[0] = tag;
+        msg[1] = v;
+        msg[2..].copy_from_slice(&cycle.to_be_bytes());
+
+        if let Some(stream) = self.stream.as_mut() {
+            if let Err(e) = stream.write_all(&msg) {
+                self.disconnect(&e);
+            }
+        }
+    }
+
+    fn receive(&mut self, block: bool) {
+        let mut buf = [0; 256];
+        let result = match self.stream.as_mut() {
+            Some(stream) => stream
+                .set_nonblocking(!block)
+                .and_then(|_| stream.read(&mut buf)),
+            None => return,
+        };
+
+        match result {
+            Ok(0) => {
+                self.disconnect(&io::Error::from(ErrorKind::UnexpectedEof));
+                return;
+            }
+            Ok(n) => self.recv_buf.extend_from_slice(&buf[..n]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                self.disconnect(&e);
+                return;
+            }
+        }
+
+        while self.recv_buf.len() >= MSG_LEN {
+            let msg: Vec<u8> = self.recv_buf.drain(..MSG_LEN).collect();
+            let mut cycle = [0; 8];
+            cycle.copy_from_slice(&msg[2..]);
+            let cycle = u64::from_be_bytes(cycle);
+
+            match msg[0] {
+                MSG_SYNC => {}
+                MSG_TRANSFER => self.pending_transfer = Some((msg[1], cycle)),
+                MSG_REPLY => self.reply = Some(msg[1]),
+                tag => error!("Unknown link cable message {}", tag),
+            }
+            if cycle > self.peer_cycle {
+                self.peer_cycle = cycle;
+            }
+        }
+    }
+
+    fn maybe_receive(&mut self) {
+        if self.cycle >= self.last_receive_cycle + RECEIVE_INTERVAL_CYCLES {
+            self.last_receive_cycle = self.cycle;
+            self.receive(false);
+        }
+    }
+
+    // A transfer clocked by the peer completes once this console has caught
+    // up to the cycle it was sent at, so both sides see it at the same time.
+    fn service_pending_transfer(&mut self) {
+        if let Some((v, cycle)) = self.pending_transfer {
+            if self.cycle >= cycle {
+                self.pending_transfer = None;
+                if let Some(out) = self.waiting_out.take() {
+                    self.send(MSG_REPLY, out, self.cycle);
+                    self.delivered = Some(v);
+                } else {
+                    self.send(MSG_REPLY, 0xFF, self.cycle);
+                }
+            }
+        }
+    }
+}
+
+impl SerialDevice for SocketLink {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.reply = None;
+        self.send(MSG_TRANSFER, out, self.cycle);
+
+        while self.stream.is_some() {
+            self.receive(true);
+            self.service_pending_transfer();
+            if let Some(v) = self.reply.take() {
+                return v;
+            }
+        }
+
+        0xFF
+    }
+
+    fn advance(&mut self, cycle: u64) {
+        self.cycle = cycle;
+
+        if cycle >= self.last_sync_cycle + SYNC_INTERVAL_CYCLES {
+            self.last_sync_cycle = cycle;
+            self.send(MSG_SYNC, 0, cycle);
+        }
+
+        self.maybe_receive();
+        self.service_pending_transfer();
+
+        while self.stream.is_some() && cycle > self.peer_cycle + MAX_LEAD_CYCLES {
+            self.receive(true);
+            self.service_pending_transfer();
+        }
+
+        // Renewed by poll_external if the port is still waiting on the
+        // external clock
+        self.waiting_out = None;
+    }
+
+    fn poll_external(&mut self, out: u8) -> Option<u8> {
+        self.waiting_out = Some(out);
+        self.maybe_receive();
+        self.service_pending_transfer();
+        self.delivered.take()
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_socket_link_transfer() {
+    use std::io::Cursor;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::{debug::Address, NullSink, System};
+
+    // Gives up after a second so a broken transport fails instead of hanging
+    const MAX_STEPS: usize = 1000;
+
+    fn run(program: &'static [u8], stream: UnixStream) -> thread::JoinHandle<(System, bool)> {
+        thread::spawn(move || {
+            let mut rom = vec![0; 0x8000];
+            rom[0x100..0x100 + program.len()].copy_from_slice(program);
+            let mut system =
+                System::new(Cursor::new(rom), Box::new(NullSink), false, None).unwrap();
+            system.attach_serial_device(Box::new(SocketLink::new(Box::new(stream))));
+            // Keep going until the transfer is done; the other thread may not
+            // have started running yet
+            for _ in 0..MAX_STEPS {
+                system.run_for_duration(&Duration::from_millis(1));
+                if system.debugger().read_mem(Address(0xFF02)).unwrap() & 0x80 == 0 {
+                    return (system, true);
+                }
+            }
+            (system, false)
+        })
+    }
+
+    let (a, b) = UnixStream::pair().unwrap();
+    // ld a, $42; ldh [SB], a; ld a, $81; ldh [SC], a; jr @
+    let master = run(
+        &[0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE],
+        a,
+    );
+    // ld a, $99; ldh [SB], a; ld a, $80; ldh [SC], a; jr @
+    let slave = run(
+        &[0x3E, 0x99, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE],
+        b,
+    );
+
+    let (mut master, master_done) = master.join().unwrap();
+    let (mut slave, slave_done) = slave.join().unwrap();
+    assert!(master_done);
+    assert!(slave_done);
+    assert_eq!(master.debugger().read_mem(Address(0xFF01)), Ok(0x99));
+    assert_eq!(slave.debugger().read_mem(Address(0xFF01)), Ok(0x42));
+}
@@ -1,10 +1,12 @@
-use std::io::Read;
+use std::io::{Error, ErrorKind, Read};
 use std::time::Duration;
 
 use log::info;
 
 use crate::{
     audio::AudioSink, cart::Cart, cpu::Cpu, debug::Debugger, input::Button, lcd::fb::Framebuffer,
+    mbc::camera::CameraSource, mbc::rtc::RtcClock, mmu::CGB_BOOT_ROM_SIZE, mmu::DMG_BOOT_ROM_SIZE,
+    serial::SerialDevice,
 };
 
 pub struct System {
@@ -16,7 +18,27 @@ impl System {
         cart_data: R,
         audio_sink: Box<dyn AudioSink + Send>,
         allow_cgb_mode: bool,
+        boot_rom: Option<Vec<u8>>,
     ) -> std::io::Result<System> {
+        if let Some(ref rom) = boot_rom {
+            match rom.len() {
+                DMG_BOOT_ROM_SIZE => {}
+                CGB_BOOT_ROM_SIZE if allow_cgb_mode => {}
+                CGB_BOOT_ROM_SIZE => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "A CGB boot ROM can't run in DMG mode",
+                    ));
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Boot ROM is neither a DMG nor a CGB boot ROM",
+                    ));
+                }
+            }
+        }
+
         let c = Cart::load(cart_data)?;
 
         info!("Name: {}", c.name());
@@ -25,7 +47,7 @@ impl System {
         info!("ROM Size: {} bytes", c.rom_size());
         info!("RAM Size: {} bytes", c.ram_size());
 
-        let cpu = Cpu::new(c, audio_sink, allow_cgb_mode);
+        let cpu = Cpu::new(c, audio_sink, allow_cgb_mode, boot_rom);
 
         Ok(System { cpu })
     }
@@ -34,6 +56,19 @@ impl System {
         self.cpu.run_for_duration(duration);
     }
 
+    pub fn run_until_cycle(&mut self, cycle: u64) {
+        self.cpu.run_until_cycle(cycle);
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cpu.cycle()
+    }
+
+    // Whether the game has run an illegal opcode, which hangs the CPU
+    pub fn is_cpu_locked(&self) -> bool {
+        self.cpu.is_locked()
+    }
+
     pub fn get_framebuffer(&self) -> &Framebuffer {
         self.cpu.mmu.lcd.get_framebuffer()
     }
@@ -42,6 +77,14 @@ impl System {
         self.cpu.mmu.pedantic = pedantic;
     }
 
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.cpu.mmu.lcd.set_sprite_limit(limit);
+    }
+
+    pub fn set_audio_low_pass(&mut self, low_pass: bool) {
+        self.cpu.mmu.audio.synth.mixer.set_low_pass(low_pass);
+    }
+
     pub fn load_cart_sram(&mut self, sram: &[u8]) {
         self.cpu.mmu.cart.set_sram(sram);
     }
@@ -50,6 +93,22 @@ impl System {
         self.cpu.mmu.cart.get_sram()
     }
 
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.cpu.mmu.cart.set_rtc_clock(clock);
+    }
+
+    // Only cartridges with an accelerometer use this. Values are in g, with
+    // positive x tilting right and positive y tilting towards the player.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.cart.set_tilt(x, y);
+    }
+
+    // Replaces the picture seen by a Pocket Camera cartridge, which otherwise
+    // sees a test pattern
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource + Send>) {
+        self.cpu.mmu.cart.set_camera_source(source);
+    }
+
     pub fn debugger(&mut self) -> Debugger {
         Debugger::new(&mut self.cpu)
     }
@@ -62,6 +121,14 @@ impl System {
     pub fn deactivate_button(&mut self, button: Button) {
         self.cpu.mmu.input.deactivate_button(button);
     }
+
+    pub fn attach_serial_device(&mut self, device: Box<dyn SerialDevice + Send>) {
+        self.cpu.mmu.serial.attach_device(device);
+    }
+
+    pub fn detach_serial_device(&mut self) -> Box<dyn SerialDevice + Send> {
+        self.cpu.mmu.serial.detach_device()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -69,3 +136,76 @@ pub enum SystemMode {
     DMG,
     CGB,
 }
+
+#[test]
+fn test_boot_rom() {
+    use crate::debug::Address;
+
+    // ld a, $42; ld [$C000], a; ...; ld a, 1; ldh [BOOT], a
+    let mut boot_rom = vec![0; DMG_BOOT_ROM_SIZE];
+    boot_rom[..5].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0]);
+    boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+    // jr @
+    let mut rom = vec![0; 0x8000];
+    rom[0] = 0xFF;
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut system = System::new(
+        std::io::Cursor::new(rom),
+        Box::new(crate::NullSink),
+        true,
+        Some(boot_rom),
+    )
+    .unwrap();
+    assert_eq!(system.debugger().read_pc(), Address(0x0000));
+    assert_eq!(system.debugger().read_mem(Address(0x0000)), Ok(0x3E));
+
+    system.run_for_duration(&Duration::from_millis(1));
+    assert_eq!(system.debugger().read_pc(), Address(0x0100));
+    assert_eq!(system.debugger().read_mem(Address(0xC000)), Ok(0x42));
+    assert_eq!(system.debugger().read_mem(Address(0x0000)), Ok(0xFF));
+
+    assert!(System::new(
+        std::io::Cursor::new(vec![0; 0x8000]),
+        Box::new(crate::NullSink),
+        true,
+        Some(vec![0; 0x200]),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_cgb_boot_rom_dmg_compatibility() {
+    use crate::debug::Address;
+
+    // ld a, $04; ldh [KEY0], a; ...; ld a, 1; ldh [BOOT], a
+    let mut boot_rom = vec![0; CGB_BOOT_ROM_SIZE];
+    boot_rom[..4].copy_from_slice(&[0x3E, 0x04, 0xE0, 0x4C]);
+    boot_rom[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+    // jr @, on a cart without CGB support
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+
+    let mut system = System::new(
+        std::io::Cursor::new(rom),
+        Box::new(crate::NullSink),
+        true,
+        Some(boot_rom),
+    )
+    .unwrap();
+    // Still a CGB while the boot ROM runs
+    assert_eq!(system.debugger().read_mem(Address(0xFF02)), Ok(0x7C));
+    assert_eq!(system.debugger().read_mem(Address(0xFF70)), Ok(0xF9));
+
+    system.run_for_duration(&Duration::from_millis(1));
+    assert_eq!(system.debugger().read_pc(), Address(0x0100));
+
+    // The cart sees a DMG's registers, but the CGB's unusable region
+    for a in &[0xFF4D, 0xFF4F, 0xFF51, 0xFF55, 0xFF68, 0xFF69, 0xFF70] {
+        assert_eq!(system.debugger().read_mem(Address(*a)), Ok(0xFF));
+    }
+    assert_eq!(system.debugger().read_mem(Address(0xFF02)), Ok(0x7E));
+    assert_eq!(system.debugger().read_mem(Address(0xFEA0)), Ok(0xAA));
+}
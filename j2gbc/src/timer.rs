@@ -1,42 +1,45 @@
 use std::cmp::min;
-use std::num::Wrapping;
 
-use super::cpu::{Interrupt, InterruptSet, CLOCK_RATE};
+use super::cpu::{Interrupt, InterruptSet};
 use super::mem::*;
 
-const DIV_INCREMENT_CYCLE_COUNT: u64 = CLOCK_RATE / 16_779;
-const TIMA_INCREMENT_CYCLE_COUNT: [u64; 4] = [
-    CLOCK_RATE / 4_096,
-    CLOCK_RATE / 262_144,
-    CLOCK_RATE / 65_536,
-    CLOCK_RATE / 16_384,
-];
+// The bit of the system counter whose falling edge clocks TIMA, for each
+// TAC clock select
+const TIMA_COUNTER_BITS: [u32; 4] = [9, 3, 5, 7];
+// Counter steps in an M-cycle
+const M_CYCLE_STEPS: u64 = 4;
+const TAC_UNUSED_BITS: u8 = 0b1111_1000;
 
+// DIV is the top half of a 16-bit counter that runs at the CPU's speed, and
+// TIMA counts falling edges on one of its bits
 #[derive(Default)]
 pub struct Timer {
-    div: u8,
+    counter: u16,
     tima: u8,
     tma: u8,
     tac: u8,
 
     double_speed: bool,
+    cycle: u64,
 
-    next_div_cycle: u64,
-    next_tima_cycle: u64,
+    // TIMA reads 0 for an M-cycle after overflowing, before TMA is loaded
+    reload_cycle: Option<u64>,
+    reloaded_cycle: Option<u64>,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            div: 0,
+            counter: 0,
             tima: 0,
             tma: 0,
             tac: 0,
 
             double_speed: false,
+            cycle: 0,
 
-            next_div_cycle: 0,
-            next_tima_cycle: 0,
+            reload_cycle: None,
+            reloaded_cycle: None,
         }
     }
 
@@ -44,75 +47,118 @@ impl Timer {
         self.double_speed = !self.double_speed;
     }
 
+    fn steps_per_cycle(&self) -> u64 {
+        if self.double_speed {
+            2
+        } else {
+            1
+        }
+    }
+
     fn tima_enabled(&self) -> bool {
         self.tac & 0b100 != 0
     }
 
-    fn tima_duration(&self) -> u64 {
-        TIMA_INCREMENT_CYCLE_COUNT[(self.tac & 0b11) as usize]
+    fn tima_period(&self) -> u64 {
+        2 << TIMA_COUNTER_BITS[(self.tac & 0b11) as usize]
+    }
+
+    // The signal whose falling edge increments TIMA
+    fn tima_signal(&self) -> bool {
+        self.tima_enabled() && u64::from(self.counter) & (self.tima_period() >> 1) != 0
+    }
+
+    fn next_tima_cycle(&self) -> u64 {
+        let period = self.tima_period();
+        let steps = period - u64::from(self.counter) % period;
+        let rate = self.steps_per_cycle();
+        self.cycle + steps.div_ceil(rate)
     }
 
     pub fn get_next_event_cycle(&self) -> u64 {
+        let reload = self.reload_cycle.unwrap_or(u64::MAX);
         if self.tima_enabled() {
-            min(self.next_div_cycle, self.next_tima_cycle)
+            min(reload, self.next_tima_cycle())
         } else {
-            self.next_div_cycle
+            reload
         }
     }
 
     pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
-        if self.next_div_cycle <= cycle {
-            self.next_div_cycle =
-                cycle + maybe_half_cycle(DIV_INCREMENT_CYCLE_COUNT, self.double_speed);
-            self.div = (Wrapping(self.div) + Wrapping(1)).0;
-        }
+        let mut inters = InterruptSet::default();
 
-        if self.tima_enabled() && self.next_tima_cycle <= cycle {
-            self.next_tima_cycle =
-                cycle + maybe_half_cycle(self.tima_duration(), self.double_speed);
+        loop {
+            let next = self.get_next_event_cycle();
+            if next > cycle {
+                break;
+            }
+            self.advance_to(next);
 
-            if self.tima == 0xFF {
+            if self.reload_cycle == Some(next) {
+                self.reload_cycle = None;
+                self.reloaded_cycle = Some(next);
                 self.tima = self.tma;
-                Interrupt::Timer.into()
+                inters.add_interrupt(Interrupt::Timer);
             } else {
-                self.tima += 1;
-                InterruptSet::default()
+                self.increment_tima();
             }
-        } else {
-            InterruptSet::default()
+        }
+        self.advance_to(cycle);
+
+        inters
+    }
+
+    fn advance_to(&mut self, cycle: u64) {
+        let steps = (cycle - self.cycle) * self.steps_per_cycle();
+        self.counter = self.counter.wrapping_add(steps as u16);
+        self.cycle = cycle;
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload_cycle = Some(self.cycle + M_CYCLE_STEPS / self.steps_per_cycle());
         }
     }
-}
 
-fn maybe_half_cycle(cycle_count: u64, double_speed: bool) -> u64 {
-    if double_speed {
-        cycle_count >> 2
-    } else {
-        cycle_count
+    fn in_reload_cycle(&self) -> bool {
+        self.reloaded_cycle == Some(self.cycle)
     }
 }
 
 impl MemDevice for Timer {
     fn read(&self, a: Address) -> Result<u8, ()> {
         match a {
-            REG_DIV => Ok(self.div),
+            REG_DIV => Ok((self.counter >> 8) as u8),
             REG_TIMA => Ok(self.tima),
             REG_TMA => Ok(self.tma),
-            REG_TAC => Ok(self.tac),
+            REG_TAC => Ok(self.tac | TAC_UNUSED_BITS),
             _ => unreachable!(),
         }
     }
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ()> {
+        // Changing what feeds the edge detector can look like a falling edge
+        let signal = self.tima_signal();
+
         match a {
             REG_DIV => {
-                self.div = 0;
+                self.counter = 0;
             }
             REG_TIMA => {
-                self.tima = v;
+                // Writing during the delay cancels the reload, but the
+                // reload itself wins over a write in the same cycle
+                if !self.in_reload_cycle() {
+                    self.reload_cycle = None;
+                    self.tima = v;
+                }
             }
             REG_TMA => {
                 self.tma = v;
+                if self.in_reload_cycle() {
+                    self.tima = v;
+                }
             }
             REG_TAC => {
                 self.tac = v;
@@ -120,6 +166,94 @@ impl MemDevice for Timer {
             _ => unreachable!(),
         }
 
+        if signal && !self.tima_signal() {
+            self.increment_tima();
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+fn fires(timer: &mut Timer, cycle: u64) -> bool {
+    timer.pump_cycle(cycle).if_() & Interrupt::Timer.bits() != 0
+}
+
+#[test]
+fn test_tima_rates() {
+    for (select, period) in [(0b00, 1024), (0b01, 16), (0b10, 64), (0b11, 256)].iter() {
+        let mut timer = Timer::new();
+        timer.write(REG_TAC, 0b100 | select).unwrap();
+        timer.pump_cycle(period * 10 - 1);
+        assert_eq!(timer.read(REG_TIMA), Ok(9));
+        timer.pump_cycle(period * 10);
+        assert_eq!(timer.read(REG_TIMA), Ok(10));
+    }
+}
+
+#[test]
+fn test_div() {
+    let mut timer = Timer::new();
+    timer.pump_cycle(256 * 3 + 10);
+    assert_eq!(timer.read(REG_DIV), Ok(3));
+
+    timer.toggle_double_speed();
+    timer.pump_cycle(256 * 4 + 10);
+    assert_eq!(timer.read(REG_DIV), Ok(5));
+
+    timer.write(REG_DIV, 0x12).unwrap();
+    assert_eq!(timer.read(REG_DIV), Ok(0));
+}
+
+#[test]
+fn test_tima_overflow_delay() {
+    let mut timer = Timer::new();
+    timer.write(REG_TMA, 0x80).unwrap();
+    timer.write(REG_TIMA, 0xFF).unwrap();
+    timer.write(REG_TAC, 0b101).unwrap();
+
+    assert!(!fires(&mut timer, 16));
+    assert_eq!(timer.read(REG_TIMA), Ok(0));
+    assert!(fires(&mut timer, 20));
+    assert_eq!(timer.read(REG_TIMA), Ok(0x80));
+
+    // Writing TMA as it is loaded is picked up straight away, but a write to
+    // TIMA is lost
+    timer.write(REG_TMA, 0x90).unwrap();
+    timer.write(REG_TIMA, 0x10).unwrap();
+    assert_eq!(timer.read(REG_TIMA), Ok(0x90));
+}
+
+#[test]
+fn test_tima_write_cancels_reload() {
+    let mut timer = Timer::new();
+    timer.write(REG_TMA, 0x80).unwrap();
+    timer.write(REG_TIMA, 0xFF).unwrap();
+    timer.write(REG_TAC, 0b101).unwrap();
+
+    timer.pump_cycle(16);
+    timer.write(REG_TIMA, 0x10).unwrap();
+    assert!(!fires(&mut timer, 20));
+    assert_eq!(timer.read(REG_TIMA), Ok(0x10));
+}
+
+#[test]
+fn test_div_and_tac_write_glitches() {
+    let mut timer = Timer::new();
+    timer.write(REG_TAC, 0b101).unwrap();
+
+    // Bit 3 of the counter is set, so resetting it is a falling edge
+    timer.pump_cycle(8);
+    timer.write(REG_DIV, 0).unwrap();
+    assert_eq!(timer.read(REG_TIMA), Ok(1));
+
+    // And so is turning the timer off while it is set
+    timer.pump_cycle(16);
+    timer.write(REG_TAC, 0b001).unwrap();
+    assert_eq!(timer.read(REG_TIMA), Ok(2));
+
+    timer.pump_cycle(40);
+    timer.write(REG_TAC, 0b101).unwrap();
+    timer.write(REG_DIV, 0).unwrap();
+    assert_eq!(timer.read(REG_TIMA), Ok(2));
+}
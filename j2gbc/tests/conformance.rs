@@ -21,9 +21,10 @@ include!(concat!(env!("OUT_DIR"), "/conformance_test_list.rs"));
 
 fn run_conformance_test(path: &str, sec_to_run: u64, expected: &[u8], expected_addr: Address) {
     let cart_file = File::open(path).unwrap();
-    let mut system = System::new(cart_file, Box::new(NullSink), false).unwrap();
+    let mut system = System::new(cart_file, Box::new(NullSink), false, None).unwrap();
 
     system.run_for_duration(&Duration::from_secs(sec_to_run));
+    assert!(!system.is_cpu_locked(), "CPU locked up");
 
     for (i, e) in expected.iter().enumerate() {
         let addr = expected_addr + Address(i as u16);
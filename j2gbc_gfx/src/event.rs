@@ -1,3 +1,6 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use enclose::enclose;
 use gdk_pixbuf::Pixbuf;
 use gtk::prelude::*;
@@ -10,20 +13,50 @@ pub fn install_event_handlers<W>(key_widget: &W, system: &SystemRef)
 where
     W: WidgetExt,
 {
-    key_widget.connect_key_press_event(enclose!((system) move |_, event| {
+    let tilt = Rc::new(RefCell::new(TiltKeys::default()));
+
+    key_widget.connect_key_press_event(enclose!((system, tilt) move |_, event| {
         if let Some(button) = keycode_to_button(event.get_keyval()) {
             system.borrow_mut().activate_button(button);
         }
+        tilt.borrow_mut().update(event.get_keyval(), true, &system);
         Inhibit(false)
     }));
-    key_widget.connect_key_release_event(enclose!((system) move |_, event| {
+    key_widget.connect_key_release_event(enclose!((system, tilt) move |_, event| {
         if let Some(button) = keycode_to_button(event.get_keyval()) {
             system.borrow_mut().deactivate_button(button);
         }
+        tilt.borrow_mut().update(event.get_keyval(), false, &system);
         Inhibit(false)
     }));
 }
 
+// Held keys tilt the cartridge all the way in that direction, for carts with
+// an accelerometer
+#[derive(Default)]
+struct TiltKeys {
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+impl TiltKeys {
+    fn update(&mut self, keycode: gdk::enums::key::Key, pressed: bool, system: &SystemRef) {
+        match keycode {
+            gdk::enums::key::j => self.left = pressed,
+            gdk::enums::key::l => self.right = pressed,
+            gdk::enums::key::i => self.up = pressed,
+            gdk::enums::key::k => self.down = pressed,
+            _ => return,
+        }
+
+        let x = f32::from(self.right as u8) - f32::from(self.left as u8);
+        let y = f32::from(self.down as u8) - f32::from(self.up as u8);
+        system.borrow_mut().set_tilt(x, y);
+    }
+}
+
 fn keycode_to_button(keycode: gdk::enums::key::Key) -> Option<Button> {
     match keycode {
         gdk::enums::key::Up => Some(Button::Up),
@@ -1,8 +1,9 @@
 use std::fs::File;
 use std::io::Read;
+use std::process;
 use std::sync::Arc;
 
-use j2gbc::{AudioSink, NullSink, System};
+use j2gbc::{AudioSink, NullSink, SocketLink, StillImageSource, System};
 
 use crate::{
     audio::{CaptureConfig, CpalSink},
@@ -28,8 +29,25 @@ pub fn load_system(args: &clap::ArgMatches<'static>) -> (System, Saver, Arc<Capt
         true
     };
 
-    let mut system = System::new(cart_file, sink, cgb_mode).unwrap();
+    let boot_rom = args.value_of("boot-rom").map(|path| {
+        let mut buf = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    });
+
+    let mut system = System::new(cart_file, sink, cgb_mode, boot_rom).unwrap();
     system.set_mmu_pedantic(!args.is_present("no-pedantic-mmu"));
+    system.set_sprite_limit(!args.is_present("no-sprite-limit"));
+    system.set_audio_low_pass(args.is_present("audio-low-pass"));
+
+    if let Some(link) = open_link(args) {
+        system.attach_serial_device(Box::new(link));
+    }
+
+    if let Some(path) = args.value_of("camera-image") {
+        let source = StillImageSource::from_pgm(File::open(path).unwrap()).unwrap();
+        system.set_camera_source(Box::new(source));
+    }
 
     let save_path = format!("{}.sav", cart_path);
     if let Ok(mut f) = File::open(&save_path) {
@@ -44,6 +62,35 @@ pub fn load_system(args: &clap::ArgMatches<'static>) -> (System, Saver, Arc<Capt
     (system, saver, capture_config)
 }
 
+fn open_link(args: &clap::ArgMatches<'static>) -> Option<SocketLink> {
+    let (addr, link) = if let Some(addr) = args.value_of("link-listen") {
+        println!("Waiting for link cable peer on {}", addr);
+        let link = if let Some(path) = addr.strip_prefix("unix:") {
+            SocketLink::listen_unix(path)
+        } else {
+            SocketLink::listen_tcp(addr)
+        };
+        (addr, link)
+    } else if let Some(addr) = args.value_of("link-connect") {
+        let link = if let Some(path) = addr.strip_prefix("unix:") {
+            SocketLink::connect_unix(path)
+        } else {
+            SocketLink::connect_tcp(addr)
+        };
+        (addr, link)
+    } else {
+        return None;
+    };
+
+    match link {
+        Ok(link) => Some(link),
+        Err(e) => {
+            eprintln!("Couldn't open link cable on {}: {}", addr, e);
+            process::exit(1);
+        }
+    }
+}
+
 pub fn parse_args() -> clap::ArgMatches<'static> {
     clap::App::new("j2gbc -- DMG and CGB emulator")
         .author("Jennifer Wilcox <jennifer@nitori.org>")
@@ -59,10 +106,43 @@ pub fn parse_args() -> clap::ArgMatches<'static> {
             .long("no-pedantic-mmu")
             .help("Disable pedantic MMU. Otherwise by default the MMU will trap if an invalid memory access occurs.")
         )
+        .arg(clap::Arg::with_name("no-sprite-limit")
+             .long("no-sprite-limit")
+             .help("Draw every sprite on a line instead of the first 10. Removes flicker in games that rely on the limit.")
+        )
+        .arg(clap::Arg::with_name("audio-low-pass")
+             .long("audio-low-pass")
+             .help("Soften the audio output's highs the way the hardware's output stage does.")
+        )
+        .arg(clap::Arg::with_name("boot-rom")
+             .long("boot-rom")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Run the DMG or CGB boot ROM in FILE before the cartridge")
+        )
         .arg(clap::Arg::with_name("no-audio")
              .long("no-audio")
              .help("Disable audio")
         )
+        .arg(clap::Arg::with_name("link-listen")
+             .long("link-listen")
+             .takes_value(true)
+             .value_name("ADDR")
+             .conflicts_with("link-connect")
+             .help("Wait for another emulator to connect a link cable at ADDR (host:port, or unix:PATH)")
+        )
+        .arg(clap::Arg::with_name("link-connect")
+             .long("link-connect")
+             .takes_value(true)
+             .value_name("ADDR")
+             .help("Connect a link cable to an emulator listening at ADDR (host:port, or unix:PATH)")
+        )
+        .arg(clap::Arg::with_name("camera-image")
+             .long("camera-image")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Show a Pocket Camera the greyscale PGM image in FILE instead of a test pattern")
+        )
         .arg(
             clap::Arg::with_name("rom")
                 .help("ROM file to load")